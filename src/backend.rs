@@ -0,0 +1,164 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::path::PathBuf;
+use std::io::{Read, Write};
+use anyhow::{Result, Context};
+use fs2::FileExt;
+
+use crate::crypto::{self, EncryptionConfig};
+use crate::{AppConfig, ClipboardStorage, HistoryItem, StorageEvent};
+
+pub mod journal;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+/// Which backend persists the history. Selected in `AppConfig`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    /// Whole-file `history.json`, rewritten on every save
+    #[default]
+    Json,
+    /// Single-file SQLite database with incremental updates
+    Sqlite,
+    /// Append-only event log, periodically compacted into a snapshot
+    Journal,
+}
+
+/// Storage for the clipboard history.
+///
+/// `persist` receives both the full in-memory state and the events queued
+/// since the last save; whole-file backends write the former, incremental
+/// ones apply only the latter.
+pub trait HistoryBackend {
+    fn load(&mut self) -> Result<ClipboardStorage>;
+
+    fn persist(&mut self, storage: &ClipboardStorage, events: &[StorageEvent]) -> Result<()>;
+
+    fn get(&mut self, hash: &str) -> Result<Option<HistoryItem>> {
+        Ok(self.load()?.history.into_iter().find(|i| i.id() == hash))
+    }
+
+    /// Refreshes `storage.history` with changes saved elsewhere. Backends
+    /// that can't tell what changed just reload everything.
+    fn sync(&mut self, storage: &mut ClipboardStorage) -> Result<()> {
+        storage.history = self.load()?.history;
+        Ok(())
+    }
+}
+
+/// Opens the backend `config` selects at its default location.
+pub fn open(config: &AppConfig) -> Result<Box<dyn HistoryBackend>> {
+    match config.backend() {
+        BackendKind::Json => Ok(Box::new(JsonBackend::encrypted(json_path()?, config.encryption.clone()))),
+        #[cfg(feature = "sqlite")]
+        BackendKind::Sqlite => Ok(Box::new(sqlite::SqliteBackend::open_default()?)),
+        #[cfg(not(feature = "sqlite"))]
+        BackendKind::Sqlite => anyhow::bail!("SQLite backend is not compiled in (enable the `sqlite` feature)"),
+        BackendKind::Journal => Ok(Box::new(journal::JournalBackend::open_default()?)),
+    }
+}
+
+pub fn json_path() -> Result<PathBuf> {
+    Ok(crate::get_data_dir()?.join("history.json"))
+}
+
+/// The original `history.json` store: locked, atomically replaced file,
+/// optionally encrypted as a whole.
+pub struct JsonBackend {
+    path: PathBuf,
+    encryption: EncryptionConfig,
+}
+
+impl JsonBackend {
+    pub fn new(path: PathBuf) -> Self {
+        Self::encrypted(path, EncryptionConfig::default())
+    }
+
+    /// Seals the file on save when `encryption` is enabled. Sealed files
+    /// are read regardless, as long as the key is available.
+    pub fn encrypted(path: PathBuf, encryption: EncryptionConfig) -> Self {
+        Self { path, encryption }
+    }
+}
+
+impl HistoryBackend for JsonBackend {
+    fn load(&mut self) -> Result<ClipboardStorage> {
+        let path = &self.path;
+        if !path.exists() {
+            return Ok(ClipboardStorage::default());
+        }
+
+        // Open file with shared lock for reading
+        let file = OpenOptions::new()
+            .read(true)
+            .open(path)
+            .context("Failed to open storage file")?;
+
+        // Acquire shared lock (allows multiple readers)
+        file.lock_shared()
+            .context("Failed to acquire shared lock on storage file")?;
+
+        let mut data = Vec::new();
+        let mut file_reader = file;
+        file_reader.read_to_end(&mut data)
+            .context("Failed to read storage file")?;
+
+        // Lock is automatically released when file goes out of scope
+        drop(file_reader);
+
+        let data = crypto::unseal(&self.encryption, data)?;
+        let content = String::from_utf8(data).context("Storage file is not valid UTF-8")?;
+
+        // Handle empty file gracefully
+        if content.trim().is_empty() {
+            return Ok(ClipboardStorage::default());
+        }
+
+        ClipboardStorage::from_json(&content)
+    }
+
+    fn persist(&mut self, storage: &ClipboardStorage, _events: &[StorageEvent]) -> Result<()> {
+        let path = &self.path;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Serialize first (before acquiring lock)
+        let content = serde_json::to_string_pretty(storage)
+            .context("Failed to serialize storage")?;
+        let content = crypto::seal(&self.encryption, content.into_bytes())?;
+
+        // Write to temporary file first (atomic operation)
+        let temp_path = path.with_extension("json.tmp");
+
+        // Create/open file with exclusive lock for writing
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temp_path)
+            .context("Failed to create temporary storage file")?;
+
+        // Acquire exclusive lock
+        file.lock_exclusive()
+            .context("Failed to acquire exclusive lock on storage file")?;
+
+        let mut file_writer = file;
+        file_writer.write_all(&content)
+            .context("Failed to write to temporary storage file")?;
+
+        // Ensure data is flushed to disk
+        file_writer.sync_all()
+            .context("Failed to sync temporary storage file")?;
+
+        // Lock is automatically released when file goes out of scope
+        drop(file_writer);
+
+        // Atomic rename (this is atomic on most filesystems)
+        fs::rename(&temp_path, path)
+            .context("Failed to rename temporary storage file")?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,342 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use anyhow::{Result, Context};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+
+use crate::{ClipboardStorage, StorageEvent};
+use super::{HistoryBackend, JsonBackend};
+
+// Fold the log into a new snapshot once it grows past this
+const COMPACT_THRESHOLD_BYTES: u64 = 256 * 1024;
+
+const LOG_FILE: &str = "events.log";
+
+/// How far a reader has replayed the log. Offsets are only meaningful
+/// within one generation; compaction starts a new one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    generation: u64,
+    offset: u64,
+}
+
+/// First line of the log, naming the snapshot it continues from.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    generation: u64,
+}
+
+/// Append-only history store.
+///
+/// Layout inside `dir`:
+/// - `snapshot-<generation>.json`: full history as of that generation
+/// - `events.log`: a `Header` line, then one `StorageEvent` per line
+///
+/// The log is only replayed on top of the snapshot whose generation its
+/// header names, so a crash mid-compaction never applies events twice.
+/// Before any snapshot exists the legacy `history.json` is the base.
+pub struct JournalBackend {
+    dir: PathBuf,
+    legacy_json: PathBuf,
+}
+
+impl JournalBackend {
+    pub fn open_default() -> Result<Self> {
+        Self::open(crate::get_data_dir()?.join("journal"), super::json_path()?)
+    }
+
+    pub fn open(dir: PathBuf, legacy_json: PathBuf) -> Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir, legacy_json })
+    }
+
+    fn snapshot_path(&self, generation: u64) -> PathBuf {
+        self.dir.join(format!("snapshot-{}.json", generation))
+    }
+
+    fn latest_snapshot(&self) -> Result<Option<u64>> {
+        let mut latest = None;
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            let generation = name.to_str()
+                .and_then(|n| n.strip_prefix("snapshot-"))
+                .and_then(|n| n.strip_suffix(".json"))
+                .and_then(|n| n.parse::<u64>().ok());
+            if let Some(g) = generation {
+                latest = latest.max(Some(g));
+            }
+        }
+        Ok(latest)
+    }
+
+    fn open_log(&self) -> Result<File> {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.dir.join(LOG_FILE))
+            .context("Failed to open history journal")
+    }
+
+    fn read_base(&self, snapshot: Option<u64>) -> Result<ClipboardStorage> {
+        match snapshot {
+            Some(generation) => {
+                let content = fs::read_to_string(self.snapshot_path(generation))
+                    .context("Failed to read history snapshot")?;
+                ClipboardStorage::from_json(&content)
+            }
+            None => JsonBackend::new(self.legacy_json.clone()).load(),
+        }
+    }
+
+    /// Snapshot plus every event in a current log. Caller holds the lock.
+    fn load_locked(&self, log: &mut File) -> Result<ClipboardStorage> {
+        let snapshot = self.latest_snapshot()?;
+        let generation = snapshot.unwrap_or(0);
+        let mut storage = self.read_base(snapshot)?;
+
+        let mut offset = 0;
+        if read_header(log)? == Some(generation) {
+            let (events, end) = read_events(log, 0)?;
+            for event in &events {
+                storage.apply(event);
+            }
+            offset = end;
+        }
+        storage.journal_cursor = Some(Cursor { generation, offset });
+        Ok(storage)
+    }
+
+    /// Folds the log into a new snapshot and starts an empty log.
+    pub fn compact(&mut self) -> Result<()> {
+        let mut log = self.open_log()?;
+        log.lock_exclusive()
+            .context("Failed to acquire exclusive lock on history journal")?;
+        self.compact_locked(&mut log)
+    }
+
+    fn compact_locked(&self, log: &mut File) -> Result<()> {
+        let previous = self.latest_snapshot()?;
+        let storage = self.load_locked(log)?;
+        let generation = previous.unwrap_or(0) + 1;
+
+        // New snapshot first; until the log header is rewritten the old
+        // log is ignored because its generation no longer matches.
+        let path = self.snapshot_path(generation);
+        let temp_path = path.with_extension("json.tmp");
+        let mut file = File::create(&temp_path)
+            .context("Failed to create history snapshot")?;
+        file.write_all(serde_json::to_string(&storage)?.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp_path, &path)
+            .context("Failed to rename history snapshot")?;
+
+        reset_log(log, generation)?;
+
+        if let Some(old) = previous {
+            let _ = fs::remove_file(self.snapshot_path(old));
+        }
+        Ok(())
+    }
+}
+
+impl HistoryBackend for JournalBackend {
+    fn load(&mut self) -> Result<ClipboardStorage> {
+        let mut log = self.open_log()?;
+        log.lock_shared()
+            .context("Failed to acquire shared lock on history journal")?;
+        self.load_locked(&mut log)
+    }
+
+    fn persist(&mut self, _storage: &ClipboardStorage, events: &[StorageEvent]) -> Result<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let mut log = self.open_log()?;
+        log.lock_exclusive()
+            .context("Failed to acquire exclusive lock on history journal")?;
+
+        let generation = self.latest_snapshot()?.unwrap_or(0);
+        if read_header(&mut log)? != Some(generation) {
+            // Fresh log, or one left behind by an interrupted compaction
+            reset_log(&mut log, generation)?;
+        } else {
+            trim_torn_tail(&mut log)?;
+        }
+
+        let mut buf = Vec::new();
+        for event in events {
+            serde_json::to_writer(&mut buf, event)?;
+            buf.push(b'\n');
+        }
+        log.seek(SeekFrom::End(0))?;
+        log.write_all(&buf).context("Failed to append to history journal")?;
+        log.sync_data().context("Failed to sync history journal")?;
+
+        if log.metadata()?.len() > COMPACT_THRESHOLD_BYTES {
+            self.compact_locked(&mut log)?;
+        }
+        Ok(())
+    }
+
+    fn sync(&mut self, storage: &mut ClipboardStorage) -> Result<()> {
+        let mut log = self.open_log()?;
+        log.lock_shared()
+            .context("Failed to acquire shared lock on history journal")?;
+
+        let generation = self.latest_snapshot()?.unwrap_or(0);
+        let current = read_header(&mut log)? == Some(generation);
+        match storage.journal_cursor {
+            Some(cursor) if cursor.generation == generation => {
+                if !current {
+                    // Nothing appended since the last compaction
+                    return Ok(());
+                }
+                let (events, end) = read_events(&mut log, cursor.offset)?;
+                for event in &events {
+                    storage.apply(event);
+                }
+                storage.journal_cursor = Some(Cursor { generation, offset: end });
+            }
+            _ => {
+                let fresh = self.load_locked(&mut log)?;
+                storage.history = fresh.history;
+                storage.journal_cursor = fresh.journal_cursor;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read_header(log: &mut File) -> Result<Option<u64>> {
+    log.seek(SeekFrom::Start(0))?;
+    let mut line = String::new();
+    BufReader::new(&mut *log).read_line(&mut line)?;
+    if !line.ends_with('\n') {
+        return Ok(None);
+    }
+    Ok(serde_json::from_str::<Header>(&line).ok().map(|h| h.generation))
+}
+
+/// Reads complete events starting at `offset` (0 means just after the
+/// header). Stops at the first torn or unparsable line and returns the
+/// offset just past the last good one.
+fn read_events(log: &mut File, offset: u64) -> Result<(Vec<StorageEvent>, u64)> {
+    log.seek(SeekFrom::Start(offset))?;
+    let mut content = Vec::new();
+    log.read_to_end(&mut content)?;
+
+    let mut pos = 0;
+    if offset == 0 {
+        match content.iter().position(|&b| b == b'\n') {
+            Some(end) => pos = end + 1,
+            None => return Ok((Vec::new(), 0)),
+        }
+    }
+
+    let mut events = Vec::new();
+    while pos < content.len() {
+        let Some(len) = content[pos..].iter().position(|&b| b == b'\n') else {
+            break;
+        };
+        let Ok(event) = serde_json::from_slice::<StorageEvent>(&content[pos..pos + len]) else {
+            break;
+        };
+        events.push(event);
+        pos += len + 1;
+    }
+    Ok((events, offset + pos as u64))
+}
+
+fn reset_log(log: &mut File, generation: u64) -> Result<()> {
+    log.set_len(0)?;
+    log.seek(SeekFrom::Start(0))?;
+    let mut header = serde_json::to_vec(&Header { generation })?;
+    header.push(b'\n');
+    log.write_all(&header)?;
+    log.sync_all()?;
+    Ok(())
+}
+
+/// Drops a partially written last line left by a crash mid-append.
+fn trim_torn_tail(log: &mut File) -> Result<()> {
+    let len = log.metadata()?.len();
+    if len == 0 {
+        return Ok(());
+    }
+    let mut last = [0u8];
+    log.seek(SeekFrom::End(-1))?;
+    log.read_exact(&mut last)?;
+    if last[0] == b'\n' {
+        return Ok(());
+    }
+
+    log.seek(SeekFrom::Start(0))?;
+    let mut content = Vec::new();
+    log.read_to_end(&mut content)?;
+    let keep = content.iter().rposition(|&b| b == b'\n').map_or(0, |p| p + 1);
+    log.set_len(keep as u64)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_dir, ClipKind};
+
+    #[test]
+    fn test_replay_and_compact() {
+        let dir = test_dir("journal-replay");
+        let mut backend = JournalBackend::open(dir.clone(), dir.join("history.json")).unwrap();
+
+        let mut storage = backend.load().unwrap();
+        for i in 0..5 {
+            storage.add(format!("content {}", i), ClipKind::Text, None, 3);
+        }
+        storage.toggle_pin(0);
+        backend.persist(&storage, storage.pending()).unwrap();
+
+        let loaded = backend.load().unwrap();
+        assert_eq!(loaded.history.len(), 3);
+        assert_eq!(loaded.history[0].content, "content 4");
+        assert!(loaded.history[0].pinned);
+
+        backend.compact().unwrap();
+        let compacted = backend.load().unwrap();
+        assert_eq!(compacted.history.len(), 3);
+        assert!(compacted.history[0].pinned);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_torn_write_and_incremental_sync() {
+        let dir = test_dir("journal-torn");
+        let mut backend = JournalBackend::open(dir.clone(), dir.join("history.json")).unwrap();
+
+        let mut writer = backend.load().unwrap();
+        writer.add("first".to_string(), ClipKind::Text, None, 50);
+        backend.persist(&writer, writer.pending()).unwrap();
+
+        let mut reader = backend.load().unwrap();
+        assert_eq!(reader.history.len(), 1);
+
+        // Simulate a crash halfway through an append
+        let mut log = OpenOptions::new().append(true).open(dir.join(LOG_FILE)).unwrap();
+        log.write_all(br#"{"added":{"content":"to"#).unwrap();
+        assert_eq!(backend.load().unwrap().history.len(), 1);
+
+        let mut writer = backend.load().unwrap();
+        writer.add("second".to_string(), ClipKind::Text, None, 50);
+        backend.persist(&writer, writer.pending()).unwrap();
+
+        backend.sync(&mut reader).unwrap();
+        assert_eq!(reader.history.len(), 2);
+        assert_eq!(reader.history[0].content, "second");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,338 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use anyhow::{Result, Context};
+use chrono::Local;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::{ClipboardStorage, HistoryItem, StorageEvent};
+use super::HistoryBackend;
+
+// Indexed columns are kept alongside the full item as JSON, so new
+// `HistoryItem` fields don't need a table migration.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS history (
+        hash TEXT PRIMARY KEY,
+        seq INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        timestamp INTEGER NOT NULL,
+        pinned INTEGER NOT NULL DEFAULT 0,
+        item TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS history_seq ON history(seq);
+    CREATE INDEX IF NOT EXISTS history_timestamp ON history(timestamp);
+";
+
+const JSON_IMPORTED_KEY: &str = "json_imported";
+// `SCHEMA_VERSION` of the stored items. Databases from before it was
+// recorded read as 0, like JSON files without the field.
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+pub struct SqliteBackend {
+    conn: Connection,
+}
+
+impl SqliteBackend {
+    pub fn default_path() -> Result<PathBuf> {
+        Ok(crate::get_data_dir()?.join("history.db"))
+    }
+
+    /// Opens `history.db`, importing `history.json` the first time.
+    pub fn open_default() -> Result<Self> {
+        Self::open_importing(&Self::default_path()?, &super::json_path()?)
+    }
+
+    /// Opens the database at `path`, importing `json_path` unless a JSON
+    /// history was imported into it before.
+    pub fn open_importing(path: &Path, json_path: &Path) -> Result<Self> {
+        let mut backend = Self::open(path)?;
+        if json_path.exists() && backend.meta(JSON_IMPORTED_KEY)?.is_none() {
+            backend.import_json(json_path)?;
+        }
+        Ok(backend)
+    }
+
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path).context("Failed to open history database")?;
+        // Daemon and front-ends share the file; wait instead of failing
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.query_row("PRAGMA journal_mode=WAL", [], |_| Ok(()))?;
+        conn.execute_batch(SCHEMA).context("Failed to create history schema")?;
+        let backend = Self { conn };
+        // New databases start out current
+        if backend.meta(SCHEMA_VERSION_KEY)?.is_none() {
+            let empty: bool = backend.conn.query_row("SELECT NOT EXISTS (SELECT 1 FROM history)", [], |row| row.get(0))?;
+            if empty {
+                set_meta(&backend.conn, SCHEMA_VERSION_KEY, &crate::SCHEMA_VERSION.to_string())?;
+            }
+        }
+        Ok(backend)
+    }
+
+    fn meta(&self, key: &str) -> Result<Option<String>> {
+        let value = self.conn
+            .query_row("SELECT value FROM meta WHERE key = ?1", params![key], |row| row.get(0))
+            .optional()?;
+        Ok(value)
+    }
+
+    fn schema_version(&self) -> Result<u32> {
+        Ok(self.meta(SCHEMA_VERSION_KEY)?.and_then(|value| value.parse().ok()).unwrap_or(0))
+    }
+
+    // Replaces every row with `storage`, e.g. once it has been migrated
+    fn rewrite(&mut self, storage: &ClipboardStorage) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM history", [])?;
+        for item in storage.history.iter().rev() {
+            insert_item(&tx, item)?;
+        }
+        set_meta(&tx, SCHEMA_VERSION_KEY, &storage.schema_version.to_string())?;
+        tx.commit().context("Failed to rewrite history")?;
+        Ok(())
+    }
+
+    /// One-shot import of a legacy `history.json`. Items already present
+    /// in the database win. Returns the number of items read.
+    pub fn import_json(&mut self, path: &Path) -> Result<usize> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let storage = if content.trim().is_empty() {
+            ClipboardStorage::default()
+        } else {
+            ClipboardStorage::from_json(&content)?
+        };
+
+        let tx = self.conn.transaction()?;
+        // Oldest first so sequence numbers grow towards the newest item
+        for item in storage.history.iter().rev() {
+            tx.execute(
+                "INSERT OR IGNORE INTO history (hash, seq, kind, timestamp, pinned, item)
+                 VALUES (?1, (SELECT COALESCE(MAX(seq), 0) + 1 FROM history), ?2, ?3, ?4, ?5)",
+                params![
+                    item.id(),
+                    item.kind.as_str(),
+                    item.timestamp.timestamp_millis(),
+                    item.pinned,
+                    serde_json::to_string(item)?,
+                ],
+            )?;
+        }
+        set_meta(&tx, JSON_IMPORTED_KEY, &Local::now().to_rfc3339())?;
+        tx.commit()?;
+
+        Ok(storage.history.len())
+    }
+}
+
+fn set_meta(conn: &Connection, key: &str, value: &str) -> Result<()> {
+    conn.execute("INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)", params![key, value])?;
+    Ok(())
+}
+
+// Inserts `item` as the newest row
+fn insert_item(conn: &Connection, item: &HistoryItem) -> Result<()> {
+    conn.execute(
+        "INSERT INTO history (hash, seq, kind, timestamp, pinned, item)
+         VALUES (?1, (SELECT COALESCE(MAX(seq), 0) + 1 FROM history), ?2, ?3, ?4, ?5)",
+        params![
+            item.id(),
+            item.kind.as_str(),
+            item.timestamp.timestamp_millis(),
+            item.pinned,
+            serde_json::to_string(item)?,
+        ],
+    )?;
+    Ok(())
+}
+
+fn decode_item(json: &str, pinned: bool) -> Result<HistoryItem> {
+    let mut item: HistoryItem = serde_json::from_str(json)
+        .context("Failed to parse stored history item")?;
+    // Pin toggles only touch the column
+    item.pinned = pinned;
+    Ok(item)
+}
+
+impl HistoryBackend for SqliteBackend {
+    fn load(&mut self) -> Result<ClipboardStorage> {
+        let mut stmt = self.conn.prepare("SELECT item, pinned FROM history ORDER BY seq DESC")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, bool>(1)?))
+        })?;
+        let mut history = Vec::new();
+        for row in rows {
+            let (json, pinned) = row?;
+            history.push(decode_item(&json, pinned)?);
+        }
+        drop(stmt);
+
+        let mut storage = ClipboardStorage { schema_version: self.schema_version()?, history, ..Default::default() };
+        // Upgraded once and stored, not on every load
+        if storage.migrate() {
+            self.rewrite(&storage)?;
+        }
+        Ok(storage)
+    }
+
+    fn persist(&mut self, _storage: &ClipboardStorage, events: &[StorageEvent]) -> Result<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let tx = self.conn.transaction()?;
+        for event in events {
+            match event {
+                StorageEvent::Added(item) => {
+                    tx.execute(
+                        "INSERT INTO history (hash, seq, kind, timestamp, pinned, item)
+                         VALUES (?1, (SELECT COALESCE(MAX(seq), 0) + 1 FROM history), ?2, ?3, ?4, ?5)
+                         ON CONFLICT(hash) DO UPDATE SET
+                             seq = excluded.seq,
+                             kind = excluded.kind,
+                             timestamp = excluded.timestamp,
+                             pinned = excluded.pinned,
+                             item = excluded.item",
+                        params![
+                            item.id(),
+                            item.kind.as_str(),
+                            item.timestamp.timestamp_millis(),
+                            item.pinned,
+                            serde_json::to_string(item)?,
+                        ],
+                    )?;
+                }
+                StorageEvent::Pinned(hash) => {
+                    tx.execute("UPDATE history SET pinned = 1 WHERE hash = ?1", params![hash])?;
+                }
+                StorageEvent::Unpinned(hash) => {
+                    tx.execute("UPDATE history SET pinned = 0 WHERE hash = ?1", params![hash])?;
+                }
+                StorageEvent::Deleted(hash) => {
+                    tx.execute("DELETE FROM history WHERE hash = ?1", params![hash])?;
+                }
+                StorageEvent::Cleared { keep_pinned: true } => {
+                    tx.execute("DELETE FROM history WHERE pinned = 0", [])?;
+                }
+                StorageEvent::Cleared { keep_pinned: false } => {
+                    tx.execute("DELETE FROM history", [])?;
+                }
+            }
+        }
+        tx.commit().context("Failed to commit history changes")?;
+        Ok(())
+    }
+
+    fn get(&mut self, hash: &str) -> Result<Option<HistoryItem>> {
+        let row: Option<(String, bool)> = self.conn
+            .query_row(
+                "SELECT item, pinned FROM history WHERE hash = ?1",
+                params![hash],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        row.map(|(json, pinned)| decode_item(&json, pinned)).transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::category::Category;
+    use crate::{test_dir, ClipKind};
+    use std::fs;
+
+    fn contents(storage: &ClipboardStorage) -> Vec<&str> {
+        storage.history.iter().map(|item| item.content.as_str()).collect()
+    }
+
+    #[test]
+    fn test_import_json_once() {
+        let dir = test_dir("sqlite-import");
+        let json_path = dir.join("history.json");
+        let mut legacy = ClipboardStorage::default();
+        legacy.add("older".to_string(), ClipKind::Text, None, 50);
+        legacy.add("newer".to_string(), ClipKind::Text, None, 50);
+        fs::write(&json_path, serde_json::to_string(&legacy).unwrap()).unwrap();
+
+        let mut backend = SqliteBackend::open_importing(&dir.join("history.db"), &json_path).unwrap();
+        let mut storage = backend.load().unwrap();
+        assert_eq!(contents(&storage), vec!["newer", "older"]);
+
+        // Deleted items don't come back from the JSON file on the next start
+        storage.remove(0);
+        backend.persist(&storage, storage.pending()).unwrap();
+        drop(backend);
+        let mut backend = SqliteBackend::open_importing(&dir.join("history.db"), &json_path).unwrap();
+        assert_eq!(contents(&backend.load().unwrap()), vec!["older"]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_persist_and_load() {
+        let dir = test_dir("sqlite-persist");
+        let mut backend = SqliteBackend::open(&dir.join("history.db")).unwrap();
+
+        let mut storage = backend.load().unwrap();
+        for content in ["a", "b", "c"] {
+            storage.add(content.to_string(), ClipKind::Text, None, 50);
+        }
+        storage.toggle_pin(1);
+        storage.remove(2);
+        backend.persist(&storage, storage.pending()).unwrap();
+
+        let mut storage = backend.load().unwrap();
+        assert_eq!(contents(&storage), vec!["c", "b"]);
+        assert!(storage.history[1].pinned);
+        let hash = storage.history[1].id().to_string();
+        assert!(backend.get(&hash).unwrap().unwrap().pinned);
+
+        // Copying an item again moves it to the front
+        storage.add("d".to_string(), ClipKind::Text, None, 50);
+        storage.add("c".to_string(), ClipKind::Text, None, 50);
+        backend.persist(&storage, storage.pending()).unwrap();
+        let mut storage = backend.load().unwrap();
+        assert_eq!(contents(&storage), vec!["c", "d", "b"]);
+
+        storage.clear(true);
+        backend.persist(&storage, storage.pending()).unwrap();
+        let mut storage = backend.load().unwrap();
+        assert_eq!(contents(&storage), vec!["b"]);
+
+        storage.clear(false);
+        backend.persist(&storage, storage.pending()).unwrap();
+        assert!(backend.load().unwrap().history.is_empty());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_migrate_stored_items() {
+        let dir = test_dir("sqlite-migrate");
+        let mut backend = SqliteBackend::open(&dir.join("history.db")).unwrap();
+
+        // A row written before items recorded their category
+        let mut storage = ClipboardStorage::default();
+        storage.add("https://example.com".to_string(), ClipKind::Text, None, 50);
+        let mut item = storage.history[0].clone();
+        item.category = None;
+        backend.persist(&storage, &[StorageEvent::Added(item.clone())]).unwrap();
+        backend.conn.execute("DELETE FROM meta WHERE key = ?1", params![SCHEMA_VERSION_KEY]).unwrap();
+
+        let loaded = backend.load().unwrap();
+        assert_eq!(loaded.schema_version, crate::SCHEMA_VERSION);
+        assert_eq!(loaded.history[0].category, Some(Category::Url));
+        // and the upgrade is stored
+        assert_eq!(backend.schema_version().unwrap(), crate::SCHEMA_VERSION);
+        assert_eq!(backend.get(item.id()).unwrap().unwrap().category, Some(Category::Url));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,19 +1,44 @@
-use asthetic_clipboard::{ClipboardStorage, AppConfig};
+use asthetic_clipboard::{ClipboardStorage, AppConfig, BackendKind, ClipKind, ClipMeta, HistoryItem, StorageEvent};
+use asthetic_clipboard::ipc::{self, Request, Response};
+use asthetic_clipboard::clipboard;
+use asthetic_clipboard::mime::{self, Offer};
+use asthetic_clipboard::blob::BlobStore;
+use asthetic_clipboard::crypto::{self, KeySource};
+use asthetic_clipboard::secrets::{self, Verdict};
+use asthetic_clipboard::source::{SourceApp, SourceDetector};
+use asthetic_clipboard::watcher::{self, PollingWatcher};
 use arboard::Clipboard;
-use std::{thread, time::Duration};
-use anyhow::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+use image::RgbaImage;
+use std::io::{BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::{thread, time::{Duration, Instant}};
+use anyhow::{Result, Context, bail};
+use chrono::Local;
 use sha2::{Digest, Sha256};
 // use std::borrow::Cow;
 // use hex; // Implicit via format!
 
-// Helper to load with retry (with exponential backoff)
-fn load_storage_with_retry() -> ClipboardStorage {
+// Helper to load with retry (with exponential backoff). None while the
+// history is locked or when it couldn't be read at all.
+fn load_storage_with_retry() -> Option<ClipboardStorage> {
     let mut attempts = 0;
     let max_attempts = 5;
     
     loop {
         match ClipboardStorage::load() {
-            Ok(s) => return s,
+            Ok(s) => {
+                HISTORY_LOADED.store(true, Ordering::SeqCst);
+                return Some(s);
+            }
+            // Retrying won't help; `history_ready` loads it once unlocked
+            Err(e) if crypto::is_locked_error(&e) => {
+                println!("History is encrypted and locked; not recording until it is unlocked");
+                return None;
+            }
             Err(e) => {
                 attempts += 1;
                 
@@ -24,8 +49,8 @@ fn load_storage_with_retry() -> ClipboardStorage {
                 }
                 
                 if attempts >= max_attempts {
-                    eprintln!("Failed to load storage after {} attempts. Returning default.", max_attempts);
-                    return ClipboardStorage::default();
+                    eprintln!("Failed to load storage after {} attempts; not recording until it loads", max_attempts);
+                    return None;
                 }
                 
                 // Exponential backoff: 50ms, 100ms, 200ms, 400ms, 800ms
@@ -37,7 +62,7 @@ fn load_storage_with_retry() -> ClipboardStorage {
 }
 
 // Helper to save with retry (with exponential backoff)
-fn save_storage_with_retry(storage: &ClipboardStorage) -> Result<()> {
+fn save_storage_with_retry(storage: &mut ClipboardStorage) -> Result<()> {
     let mut attempts = 0;
     let max_attempts = 5;
     
@@ -65,82 +90,492 @@ fn save_storage_with_retry(storage: &ClipboardStorage) -> Result<()> {
     }
 }
 
+// How often expired items and retention limits are checked
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(15);
+// How often the images dir is checked against the history
+const GC_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// Image files are written just before their item is added
+const GC_GRACE: Duration = Duration::from_secs(60);
+
+type SharedStorage = Arc<Mutex<ClipboardStorage>>;
+type Subscribers = Arc<Mutex<Vec<UnixStream>>>;
+
+// Whether the history was actually read from disk. While it is locked or
+// failed to load, the storage is an empty stand-in: saving it would wipe
+// the real history, and next to it every image would look orphaned.
+static HISTORY_LOADED: AtomicBool = AtomicBool::new(false);
+
+// Whether the history is loaded, loading it if the key has become
+// available or the store readable since. Must not be called with
+// `storage` locked.
+fn history_ready(storage: &SharedStorage) -> bool {
+    if HISTORY_LOADED.load(Ordering::SeqCst) {
+        return true;
+    }
+    let config = AppConfig::load().unwrap_or_default();
+    if crypto::is_locked(&config.encryption) {
+        return false;
+    }
+    match load_storage_with_retry() {
+        Some(loaded) => {
+            *storage.lock().unwrap() = loaded;
+            true
+        }
+        None => false,
+    }
+}
+
+// Persist queued changes and push them to subscribed front-ends
+fn commit(storage: &mut ClipboardStorage, subscribers: &Subscribers) {
+    let events: Vec<StorageEvent> = storage.pending().to_vec();
+    // Never save the stand-in over a history that didn't load
+    if events.is_empty() || !HISTORY_LOADED.load(Ordering::SeqCst) {
+        return;
+    }
+    if let Err(e) = save_storage_with_retry(storage) {
+        eprintln!("Failed to save history after retries: {}", e);
+    }
+
+    let mut subs = subscribers.lock().unwrap();
+    subs.retain_mut(|stream| {
+        events.iter().all(|event| ipc::write_message(stream, &Response::Event(event.clone())).is_ok())
+    });
+}
+
+fn handle_request(request: Request, storage: &SharedStorage, subscribers: &Subscribers) -> Result<Response> {
+    if let Request::Unlock { passphrase } = &request {
+        let config = AppConfig::load().unwrap_or_default();
+        crypto::unlock(&config.encryption, passphrase.as_deref())?;
+        if !history_ready(storage) {
+            bail!("History could not be loaded after unlocking");
+        }
+        println!("History unlocked");
+        return Ok(Response::Ok);
+    }
+    if !history_ready(storage) {
+        if crypto::is_locked(&AppConfig::load().unwrap_or_default().encryption) {
+            return Ok(Response::Locked);
+        }
+        bail!("History could not be loaded");
+    }
+
+    // Both are slow, writing the clipboard up to the whole X11 selection
+    // timeout, so they work on a copy of the item without holding the lock
+    match request {
+        Request::Copy { hash } => {
+            let encryption = AppConfig::load().unwrap_or_default().encryption;
+            // The polling loop picks the change up and moves the item to the top
+            clipboard::write(&find_item(storage, &hash)?, &encryption)?;
+            return Ok(Response::Ok);
+        }
+        Request::Image { hash } => {
+            let item = find_item(storage, &hash)?;
+            if item.kind != ClipKind::Image {
+                bail!("History item {} is not an image", hash);
+            }
+            let encryption = AppConfig::load().unwrap_or_default().encryption;
+            return Ok(Response::data(crypto::read_file(&encryption, std::path::Path::new(&item.content))?));
+        }
+        _ => {}
+    }
+
+    let mut s = storage.lock().unwrap();
+    let response = match request {
+        Request::List => Response::Items(s.history.clone()),
+        Request::Get { hash } => Response::Item(s.position(&hash).map(|i| s.history[i].clone())),
+        Request::Pin { hash, pinned } => {
+            let Some(i) = s.position(&hash) else { bail!("No history item {}", hash) };
+            if s.history[i].pinned != pinned {
+                s.toggle_pin(i);
+            }
+            Response::Ok
+        }
+        Request::Delete { hash } => {
+            let Some(i) = s.position(&hash) else { bail!("No history item {}", hash) };
+            s.remove(i);
+            Response::Ok
+        }
+        Request::Clear { keep_pinned } => {
+            s.clear(keep_pinned);
+            Response::Ok
+        }
+        // Handled above and by the connection loop
+        Request::Copy { .. } | Request::Image { .. } | Request::Unlock { .. } | Request::Subscribe => Response::Ok,
+    };
+    commit(&mut s, subscribers);
+    Ok(response)
+}
+
+fn find_item(storage: &SharedStorage, hash: &str) -> Result<HistoryItem> {
+    let s = storage.lock().unwrap();
+    let Some(i) = s.position(hash) else { bail!("No history item {}", hash) };
+    Ok(s.history[i].clone())
+}
+
+fn serve_client(stream: UnixStream, storage: SharedStorage, subscribers: Subscribers) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    while let Some(request) = ipc::read_message::<Request>(&mut reader)? {
+        if let Request::Subscribe = request {
+            ipc::write_message(&mut writer, &Response::Ok)?;
+            // A stuck subscriber must not stall everyone else
+            writer.set_write_timeout(Some(Duration::from_secs(1)))?;
+            subscribers.lock().unwrap().push(writer);
+            return Ok(());
+        }
+        let response = handle_request(request, &storage, &subscribers)
+            .unwrap_or_else(|e| Response::Error(e.to_string()));
+        ipc::write_response(&mut writer, &response)?;
+    }
+    Ok(())
+}
+
+fn start_ipc_server(storage: SharedStorage, subscribers: Subscribers) -> Result<()> {
+    let path = ipc::socket_path()?;
+    if path.exists() {
+        if UnixStream::connect(&path).is_ok() {
+            bail!("Another clipboard daemon is already listening on {}", path.display());
+        }
+        // Left behind by a daemon that didn't shut down cleanly
+        std::fs::remove_file(&path)?;
+    }
+    let listener = UnixListener::bind(&path)
+        .with_context(|| format!("Failed to bind {}", path.display()))?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            let storage = storage.clone();
+            let subscribers = subscribers.clone();
+            thread::spawn(move || {
+                if let Err(e) = serve_client(stream, storage, subscribers) {
+                    eprintln!("IPC client error: {}", e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+// Why a new clip must not be recorded, if it mustn't: private mode is on,
+// password managers mark copied secrets, users can ignore apps, and a
+// history that is locked or didn't load can't be written. Either way nothing may reach the
+// history or the images dir.
+fn skip_reason(config: &AppConfig, source: Option<&SourceApp>, offer: &Offer, storage: &SharedStorage) -> Option<String> {
+    if config.is_private(Local::now()) {
+        return Some("private mode is on".to_string());
+    }
+    if !history_ready(storage) {
+        let reason = if crypto::is_locked(&config.encryption) { "history is locked" } else { "history could not be loaded" };
+        return Some(reason.to_string());
+    }
+    if config.secrets.respect_password_manager_hint && offer.has_password_manager_hint() {
+        return Some("marked secret by a password manager".to_string());
+    }
+    source
+        .filter(|app| config.is_ignored(app))
+        .map(|app| format!("copied from ignored app {}", app.class))
+}
+
+// Expiring items and retention limits must be enforced even while the
+// clipboard stays quiet, so they are not left to the polling loop. The
+// first pass runs right away, at startup, and so does the first garbage
+// collection of the images dir.
+fn start_cleanup_timer(storage: SharedStorage, subscribers: Subscribers) {
+    thread::spawn(move || {
+        let mut last_gc: Option<Instant> = None;
+        loop {
+            // Also retries a failed load now and then
+            if !history_ready(&storage) {
+                thread::sleep(CLEANUP_INTERVAL);
+                continue;
+            }
+            let config = AppConfig::load().unwrap_or_default();
+            let now = Local::now();
+            let mut s = storage.lock().unwrap();
+            let expired = s.remove_expired(now);
+            let removed = s.apply_retention(&config.retention, now);
+            if expired > 0 || !removed.is_empty() {
+                commit(&mut s, &subscribers);
+            }
+            if last_gc.is_none_or(|at| at.elapsed() >= GC_INTERVAL) {
+                last_gc = Some(Instant::now());
+                collect_garbage(&mut s, &subscribers);
+            }
+            drop(s);
+            thread::sleep(CLEANUP_INTERVAL);
+        }
+    });
+}
+
+fn collect_garbage(storage: &mut ClipboardStorage, subscribers: &Subscribers) {
+    let report = BlobStore::open()
+        .and_then(|blobs| storage.collect_garbage(&blobs, GC_GRACE));
+    match report {
+        Ok(report) => {
+            if report.orphans > 0 {
+                println!("Deleted {} orphaned image(s), freeing {} KiB", report.orphans, report.bytes_freed / 1024);
+            }
+            if report.missing > 0 {
+                println!("Removed {} image item(s) whose file was missing", report.missing);
+                commit(storage, subscribers);
+            }
+        }
+        Err(e) => eprintln!("Failed to collect orphaned images: {}", e),
+    }
+}
+
+// Reads a passphrase from the terminal without echoing it
+fn prompt_passphrase(prompt: &str) -> Result<String> {
+    eprint!("{}", prompt);
+    std::io::stderr().flush()?;
+    terminal::enable_raw_mode()?;
+    let result = read_hidden_line();
+    terminal::disable_raw_mode()?;
+    eprintln!();
+    result
+}
+
+fn read_hidden_line() -> Result<String> {
+    let mut line = String::new();
+    loop {
+        let Event::Key(key) = event::read()? else { continue };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Enter => return Ok(line),
+            KeyCode::Esc => bail!("Cancelled"),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => bail!("Cancelled"),
+            KeyCode::Backspace => {
+                line.pop();
+            }
+            KeyCode::Char(c) => line.push(c),
+            _ => {}
+        }
+    }
+}
+
+// `daemon encrypt [--passphrase]`: encrypt the existing history and its
+// images, keeping the key in the keyring or deriving it from a passphrase
+fn encrypt_store(use_passphrase: bool) -> Result<()> {
+    let mut config = AppConfig::load()?;
+    if config.encryption.enabled {
+        bail!("History is already encrypted");
+    }
+    // Checked before a key is made for nothing
+    if config.storage_backend != BackendKind::Json {
+        bail!("{}", crypto::UNSUPPORTED_BACKEND);
+    }
+    let (source, passphrase) = if use_passphrase {
+        let passphrase = prompt_passphrase("New passphrase: ")?;
+        if passphrase.is_empty() {
+            bail!("Passphrase must not be empty");
+        }
+        if prompt_passphrase("Repeat passphrase: ")? != passphrase {
+            bail!("Passphrases don't match");
+        }
+        (KeySource::Passphrase, Some(passphrase))
+    } else {
+        (KeySource::Keyring, None)
+    };
+    crypto::create_key(source, passphrase.as_deref())?;
+
+    config.encryption.key_source = source;
+    config.save()?;
+    let images = crypto::migrate_store(true)?;
+    println!("Encrypted the history and {} images", images);
+    Ok(())
+}
+
+// `daemon decrypt`: turn encryption off again, rewriting everything as plaintext
+fn decrypt_store() -> Result<()> {
+    let config = AppConfig::load()?;
+    if !config.encryption.enabled {
+        bail!("History is not encrypted");
+    }
+    let passphrase = match config.encryption.key_source {
+        KeySource::Passphrase => Some(prompt_passphrase("Passphrase: ")?),
+        KeySource::Keyring => None,
+    };
+    crypto::unlock(&config.encryption, passphrase.as_deref())?;
+    let images = crypto::migrate_store(false)?;
+    println!("Decrypted the history and {} images", images);
+    Ok(())
+}
+
 fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        // The running daemon would keep writing with the old setting
+        if ipc::DaemonClient::connect().is_ok() {
+            bail!("Stop the clipboard daemon first");
+        }
+        return match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+            ["encrypt"] => encrypt_store(false),
+            ["encrypt", "--passphrase"] => encrypt_store(true),
+            ["decrypt"] => decrypt_store(),
+            _ => bail!("Usage: daemon [encrypt [--passphrase] | decrypt]"),
+        };
+    }
+
     let mut clipboard = Clipboard::new()?;
+    let mut sources = SourceDetector::new();
     let mut last_text_content = String::new();
     let mut last_image_hash = String::new();
+    let mut last_files_content = String::new();
 
-    // Initial check (optional, let's keep it simple and just start loop)
-    println!("Clipboard daemon started...");
+    let config = AppConfig::load().unwrap_or_default();
+    if !SourceDetector::is_supported() {
+        println!("This compositor doesn't say which app copied a clip; ignored_apps has no effect");
+    }
+    if config.backend() != config.storage_backend {
+        println!("Keeping the encrypted history in history.json; the {:?} backend doesn't support encryption", config.storage_backend);
+    }
+
+    // The daemon owns the history; front-ends talk to it over the socket
+    let storage: SharedStorage = Arc::new(Mutex::new(load_storage_with_retry().unwrap_or_default()));
+    let subscribers: Subscribers = Arc::new(Mutex::new(Vec::new()));
+    start_ipc_server(storage.clone(), subscribers.clone())?;
+    start_cleanup_timer(storage.clone(), subscribers.clone());
 
+    let mut watcher = watcher::detect();
+    println!("Clipboard daemon started ({} watcher)...", watcher.name());
+
+    // The first pass records whatever is on the clipboard right now
+    let mut first = true;
     loop {
-        thread::sleep(Duration::from_millis(500));
+        if !first && let Err(e) = watcher.wait() {
+            eprintln!("Clipboard watcher '{}' failed ({}), falling back to polling", watcher.name(), e);
+            watcher = Box::new(PollingWatcher::new(watcher::POLL_INTERVAL));
+        }
+        first = false;
 
         // 1. Check Text
-        match clipboard.get_text() {
-            Ok(content) => {
-                if content != last_text_content && !content.trim().is_empty() {
-                    println!("Detected text change");
-                    let mut storage = load_storage_with_retry();
-                    let config = AppConfig::load().unwrap_or_default();
-                    storage.add(content.clone(), "text".to_string(), None, config.max_history);
-                    if let Err(e) = save_storage_with_retry(&storage) {
-                        eprintln!("Failed to save history after retries: {}", e);
+        if let Ok(content) = clipboard.get_text()
+            && content != last_text_content
+            && !content.trim().is_empty()
+        {
+            println!("Detected text change");
+            let source = sources.detect();
+            let offer = Offer::current();
+            let config = AppConfig::load().unwrap_or_default();
+            let verdict = match skip_reason(&config, source.as_ref(), &offer, &storage) {
+                Some(reason) => Verdict::Skip { rule: reason },
+                None => secrets::check(&content, &config.secrets),
+            };
+            match verdict {
+                Verdict::Clean => {
+                    // HTML, RTF, URI lists etc. offered alongside the text
+                    let mut representations = offer.representations();
+                    let kind = mime::kind_for(&representations);
+                    let entry_content = if kind == ClipKind::Files {
+                        // File managers offer the paths as text too; store them tidily
+                        let paths = mime::uri_list_paths(&representations[mime::URI_LIST]);
+                        mime::add_file_formats(&mut representations, &paths);
+                        last_files_content = mime::files_content(&paths);
+                        last_files_content.clone()
+                    } else {
+                        last_files_content.clear();
+                        content.clone()
+                    };
+                    let meta = ClipMeta { representations, source, ..Default::default() };
+                    let mut s = storage.lock().unwrap();
+                    s.add_with_meta(entry_content, kind, None, meta, config.max_history);
+                    commit(&mut s, &subscribers);
+                }
+                Verdict::Skip { rule } => {
+                    println!("Not recording clip: {}", rule);
+                    last_files_content.clear();
+                }
+                Verdict::Redact { content: redacted, expires_at, rules } => {
+                    println!("Recording clip redacted for secret rules: {}", rules.join(", "));
+                    let meta = ClipMeta { expires_at, source, sensitive: true, ..Default::default() };
+                    let mut s = storage.lock().unwrap();
+                    s.add_with_meta(redacted, ClipKind::Text, None, meta, config.max_history);
+                    commit(&mut s, &subscribers);
+                    last_files_content.clear();
+                }
+            }
+            last_text_content = content;
+            last_image_hash.clear();
+            continue; // Skip image check if text was found (optimization)
+        }
+
+        // 2. Check Files copied without a text form
+        if let Ok(paths) = clipboard.get().file_list()
+            && !paths.is_empty()
+        {
+            let content = mime::files_content(&paths);
+            if content != last_files_content {
+                println!("Detected copied files");
+                let source = sources.detect();
+                let offer = Offer::current();
+                let config = AppConfig::load().unwrap_or_default();
+                if let Some(reason) = skip_reason(&config, source.as_ref(), &offer, &storage) {
+                    println!("Not recording files: {}", reason);
+                } else {
+                    let mut representations = offer.representations();
+                    mime::add_file_formats(&mut representations, &paths);
+                    let meta = ClipMeta { representations, source, ..Default::default() };
+                    let mut s = storage.lock().unwrap();
+                    s.add_with_meta(content.clone(), ClipKind::Files, None, meta, config.max_history);
+                    commit(&mut s, &subscribers);
+                }
+                last_files_content = content;
+                last_text_content.clear();
+                last_image_hash.clear();
+            }
+            continue;
+        }
+
+        // 3. Check Image
+        if let Ok(image_data) = clipboard.get_image() {
+            // Compute hash
+            let mut hasher = Sha256::new();
+            hasher.update(&image_data.bytes);
+            let hash = hex::encode(hasher.finalize());
+
+            if hash != last_image_hash && !hash.is_empty() {
+                println!("Detected image change: {}", hash);
+                let source = sources.detect();
+                let offer = Offer::current();
+                let config = AppConfig::load().unwrap_or_default();
+
+                // Save Image to Disk
+                if let Some(reason) = skip_reason(&config, source.as_ref(), &offer, &storage) {
+                    println!("Not recording image: {}", reason);
+                } else if let Ok(blobs) = BlobStore::open() {
+                    // Stored as PNG, encrypted if the store is, with its thumbnails
+                    let saved = RgbaImage::from_raw(
+                        image_data.width as u32,
+                        image_data.height as u32,
+                        image_data.bytes.into_owned(),
+                    )
+                    .context("Clipboard image has the wrong size")
+                    .and_then(|image| blobs.put(&config.encryption, &hash, &image));
+                    match saved {
+                        Err(e) => eprintln!("Failed to save image to disk: {}", e),
+                        Ok((file_path, image)) => {
+                            // Add to storage
+                            let mut s = storage.lock().unwrap();
+                            // For images, 'content' is the absolute path to the file
+                            let meta = ClipMeta { source, image: Some(image), ..Default::default() };
+                            s.add_with_meta(
+                                file_path.to_string_lossy().to_string(),
+                                ClipKind::Image,
+                                Some(hash.clone()),
+                                meta,
+                                config.max_history
+                            );
+                            commit(&mut s, &subscribers);
+                        }
                     }
-                    last_text_content = content;
-                    last_image_hash.clear(); 
-                    continue; // Skip image check if text was found (optimization)
                 }
-            },
-            Err(_) => {}
-        }
-
-        // 2. Check Image
-        match clipboard.get_image() {
-             Ok(image_data) => {
-                 // Compute hash
-                 let mut hasher = Sha256::new();
-                 hasher.update(&image_data.bytes);
-                 let hash = hex::encode(hasher.finalize());
-
-                 if hash != last_image_hash && !hash.is_empty() {
-                     println!("Detected image change: {}", hash);
-                     
-                     // Save Image to Disk
-                     if let Ok(images_dir) = ClipboardStorage::get_images_dir() {
-                         let file_name = format!("{}.png", hash);
-                         let file_path = images_dir.join(&file_name);
-                         
-                         // Save using image crate
-                         if let Err(e) = image::save_buffer(
-                             &file_path, 
-                             &image_data.bytes, 
-                             image_data.width as u32, 
-                             image_data.height as u32, 
-                             image::ColorType::Rgba8
-                         ) {
-                             eprintln!("Failed to save image to disk: {}", e);
-                         } else {
-                             // Add to storage
-                             let mut storage = load_storage_with_retry();
-                             let config = AppConfig::load().unwrap_or_default();
-                             // For images, 'content' is the absolute path to the file
-                             storage.add(
-                                 file_path.to_string_lossy().to_string(), 
-                                 "image".to_string(), 
-                                 Some(hash.clone()),
-                                 config.max_history
-                             );
-                             if let Err(e) = save_storage_with_retry(&storage) {
-                                 eprintln!("Failed to save history after retries: {}", e);
-                             }
-                         }
-                     }
-                     last_image_hash = hash;
-                     last_text_content.clear(); // Clear text as clipboard now has image
-                 }
-             },
-             Err(_) => {}
+                last_image_hash = hash;
+                last_text_content.clear(); // Clear text as clipboard now has image
+                last_files_content.clear();
+            }
         }
     }
 }
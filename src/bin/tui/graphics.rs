@@ -0,0 +1,301 @@
+//! Image previews in the terminal: Kitty graphics, Sixel and iTerm2
+//! inline images where supported, Unicode half blocks everywhere else.
+
+use std::io::{self, Write};
+use crossterm::{cursor::MoveTo, queue};
+use image::{imageops::FilterType, RgbaImage};
+use ratatui::{
+    layout::Rect,
+    style::{Color, Style},
+    text::{Line, Span},
+};
+
+// Kitty wants its base64 payload in chunks of at most this many bytes
+const KITTY_CHUNK: usize = 4096;
+
+// Used when the terminal doesn't report its size in pixels
+const DEFAULT_CELL_SIZE: (u32, u32) = (8, 16);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Kitty,
+    Sixel,
+    Iterm2,
+    /// Plain text cells, drawn through ratatui like everything else
+    HalfBlocks,
+}
+
+impl Protocol {
+    /// Guesses from the environment. `ASTHETIC_TUI_GRAPHICS` (kitty, sixel,
+    /// iterm2 or blocks) overrides the guess for terminals we don't know.
+    pub fn detect() -> Self {
+        let var = |name: &str| std::env::var(name).unwrap_or_default();
+        match var("ASTHETIC_TUI_GRAPHICS").as_str() {
+            "kitty" => return Protocol::Kitty,
+            "sixel" => return Protocol::Sixel,
+            "iterm2" => return Protocol::Iterm2,
+            "blocks" => return Protocol::HalfBlocks,
+            _ => {}
+        }
+
+        // Inside tmux/screen escapes would need wrapping; stay safe
+        if !var("TMUX").is_empty() || var("TERM").starts_with("screen") {
+            return Protocol::HalfBlocks;
+        }
+        let term = var("TERM");
+        let program = var("TERM_PROGRAM");
+        if !var("KITTY_WINDOW_ID").is_empty()
+            || term.contains("kitty")
+            || matches!(program.as_str(), "WezTerm" | "ghostty")
+        {
+            Protocol::Kitty
+        } else if program == "iTerm.app" || var("LC_TERMINAL") == "iTerm2" {
+            Protocol::Iterm2
+        } else if term.contains("sixel") || term.starts_with("foot") || term.starts_with("mlterm")
+            || term.contains("contour")
+        {
+            Protocol::Sixel
+        } else {
+            Protocol::HalfBlocks
+        }
+    }
+}
+
+/// Pixel size of one terminal cell.
+pub fn cell_size() -> (u32, u32) {
+    match crossterm::terminal::window_size() {
+        Ok(size) if size.width > 0 && size.height > 0 && size.columns > 0 && size.rows > 0 => (
+            (size.width / size.columns) as u32,
+            (size.height / size.rows) as u32,
+        ),
+        _ => DEFAULT_CELL_SIZE,
+    }
+}
+
+/// The part of `area` an image of `width`x`height` pixels covers when
+/// scaled to fit (never up), anchored at the top left.
+pub fn fit(width: u32, height: u32, area: Rect, cell: (u32, u32)) -> Rect {
+    let max_w = area.width as f64 * cell.0 as f64;
+    let max_h = area.height as f64 * cell.1 as f64;
+    let scale = (max_w / width.max(1) as f64).min(max_h / height.max(1) as f64).min(1.0);
+    let cols = ((width as f64 * scale) / cell.0 as f64).ceil().max(1.0) as u16;
+    let rows = ((height as f64 * scale) / cell.1 as f64).ceil().max(1.0) as u16;
+    Rect::new(area.x, area.y, cols.min(area.width), rows.min(area.height))
+}
+
+/// Draws an image straight to the terminal with a graphics protocol.
+/// `png` is the image as PNG, `image` a decoded copy for Sixel.
+pub fn draw(
+    out: &mut impl Write,
+    protocol: Protocol,
+    png: &[u8],
+    image: &RgbaImage,
+    area: Rect,
+) -> io::Result<()> {
+    let cell = cell_size();
+    let target = fit(image.width(), image.height(), area, cell);
+    queue!(out, MoveTo(target.x, target.y))?;
+    match protocol {
+        Protocol::Kitty => {
+            let encoded = base64(png);
+            let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(KITTY_CHUNK).collect();
+            for (i, chunk) in chunks.iter().enumerate() {
+                let more = u8::from(i + 1 < chunks.len());
+                if i == 0 {
+                    // PNG payload, placed over the given cells, cursor left
+                    // alone, no replies that would show up as key presses
+                    write!(out, "\x1b_Gf=100,a=T,c={},r={},C=1,q=2,m={};", target.width, target.height, more)?;
+                } else {
+                    write!(out, "\x1b_Gm={};", more)?;
+                }
+                out.write_all(chunk)?;
+                write!(out, "\x1b\\")?;
+            }
+        }
+        Protocol::Iterm2 => {
+            write!(
+                out,
+                "\x1b]1337;File=inline=1;size={};width={};height={};preserveAspectRatio=1:{}\x07",
+                png.len(), target.width, target.height, base64(png)
+            )?;
+        }
+        Protocol::Sixel => {
+            let pixels = scale_to_fit(image, target.width as u32 * cell.0, target.height as u32 * cell.1);
+            out.write_all(sixel(&pixels).as_bytes())?;
+        }
+        Protocol::HalfBlocks => {}
+    }
+    out.flush()
+}
+
+/// Shrinks `image` to fit within `max_w`x`max_h`, keeping its aspect ratio.
+fn scale_to_fit(image: &RgbaImage, max_w: u32, max_h: u32) -> RgbaImage {
+    let (w, h) = image.dimensions();
+    if w <= max_w && h <= max_h {
+        return image.clone();
+    }
+    let scale = (max_w as f64 / w as f64).min(max_h as f64 / h as f64);
+    let new_w = ((w as f64 * scale) as u32).max(1);
+    let new_h = ((h as f64 * scale) as u32).max(1);
+    image::imageops::resize(image, new_w, new_h, FilterType::Triangle)
+}
+
+/// Removes images drawn with `protocol`. Sixel and iTerm2 images are just
+/// cell contents, so for those the caller redraws the screen instead.
+pub fn clear(out: &mut impl Write, protocol: Protocol) -> io::Result<()> {
+    if protocol == Protocol::Kitty {
+        write!(out, "\x1b_Ga=d,q=2\x1b\\")?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+/// The image as rows of `▀` cells, the top pixel in the foreground colour
+/// and the bottom one in the background, fitted to `width`x`height` cells.
+pub fn half_blocks(image: &RgbaImage, width: u16, height: u16) -> Vec<Line<'static>> {
+    if width == 0 || height == 0 {
+        return Vec::new();
+    }
+    // Two pixels per cell vertically, roughly square each
+    let pixels = scale_to_fit(image, width as u32, height as u32 * 2);
+    let (w, h) = pixels.dimensions();
+
+    (0..h.div_ceil(2))
+        .map(|row| {
+            let spans: Vec<Span> = (0..w)
+                .map(|x| {
+                    let top = color(pixels.get_pixel(x, row * 2));
+                    let bottom = if row * 2 + 1 < h {
+                        color(pixels.get_pixel(x, row * 2 + 1))
+                    } else {
+                        Color::Reset
+                    };
+                    Span::styled("▀", Style::default().fg(top).bg(bottom))
+                })
+                .collect();
+            Line::from(spans)
+        })
+        .collect()
+}
+
+fn color(pixel: &image::Rgba<u8>) -> Color {
+    let [r, g, b, a] = pixel.0;
+    if a < 128 {
+        Color::Reset
+    } else {
+        Color::Rgb(r, g, b)
+    }
+}
+
+/// Sixel data for `image`, quantized to a 6x6x6 colour cube. Transparent
+/// pixels are left unpainted.
+fn sixel(image: &RgbaImage) -> String {
+    let (width, height) = image.dimensions();
+    let level = |v: u8| (v as u32 * 5 + 127) / 255;
+    let index = |p: &image::Rgba<u8>| -> Option<u32> {
+        let [r, g, b, a] = p.0;
+        (a >= 128).then(|| level(r) * 36 + level(g) * 6 + level(b))
+    };
+
+    // P2=1: pixels we don't set keep the background
+    let mut out = format!("\x1bP0;1q\"1;1;{};{}", width, height);
+    for i in 0..216 {
+        let pct = |l: u32| l * 100 / 5;
+        out.push_str(&format!("#{};2;{};{};{}", i, pct(i / 36), pct(i / 6 % 6), pct(i % 6)));
+    }
+
+    for band in (0..height).step_by(6) {
+        let rows = (height - band).min(6);
+        let mut colors: Vec<u32> = Vec::new();
+        for y in band..band + rows {
+            for x in 0..width {
+                if let Some(c) = index(image.get_pixel(x, y))
+                    && !colors.contains(&c)
+                {
+                    colors.push(c);
+                }
+            }
+        }
+        for c in colors {
+            out.push_str(&format!("#{}", c));
+            let mut run_char = '\0';
+            let mut run_len = 0;
+            for x in 0..width {
+                let mut bits = 0u8;
+                for dy in 0..rows {
+                    if index(image.get_pixel(x, band + dy)) == Some(c) {
+                        bits |= 1 << dy;
+                    }
+                }
+                let ch = (63 + bits) as char;
+                if ch == run_char {
+                    run_len += 1;
+                } else {
+                    push_run(&mut out, run_char, run_len);
+                    run_char = ch;
+                    run_len = 1;
+                }
+            }
+            push_run(&mut out, run_char, run_len);
+            // Back to the start of the band for the next colour
+            out.push('$');
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\");
+    out
+}
+
+fn push_run(out: &mut String, ch: char, len: usize) {
+    match len {
+        0 => {}
+        1..=3 => out.extend(std::iter::repeat_n(ch, len)),
+        _ => out.push_str(&format!("!{}{}", len, ch)),
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b"Man"), "TWFu");
+        assert_eq!(base64(b"Ma"), "TWE=");
+        assert_eq!(base64(b"M"), "TQ==");
+    }
+
+    #[test]
+    fn test_sixel() {
+        // 2x1 opaque red then transparent: one colour, one band
+        let mut image = RgbaImage::new(2, 1);
+        image.put_pixel(0, 0, image::Rgba([255, 0, 0, 255]));
+        let data = sixel(&image);
+        assert!(data.starts_with("\x1bP0;1q\"1;1;2;1"));
+        assert!(data.ends_with("#180@?$-\x1b\\"));
+    }
+
+    #[test]
+    fn test_fit() {
+        let area = Rect::new(0, 0, 40, 10);
+        assert_eq!(fit(800, 160, area, (8, 16)), Rect::new(0, 0, 40, 4));
+        assert_eq!(fit(16, 16, area, (8, 16)), Rect::new(0, 0, 2, 1));
+    }
+}
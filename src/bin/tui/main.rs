@@ -0,0 +1,789 @@
+mod graphics;
+
+use asthetic_clipboard::{AppConfig, ClipboardStorage, ClipKind, HistoryItem, SearchHit, SearchQuery};
+use asthetic_clipboard::blob::{BlobStore, ImageMeta, ThumbnailSize};
+use asthetic_clipboard::category::{self, Category};
+use asthetic_clipboard::clipboard;
+use asthetic_clipboard::crypto::{self, EncryptionConfig, KeySource};
+use asthetic_clipboard::highlight::{self, Token};
+use asthetic_clipboard::search::highlight_runs;
+use asthetic_clipboard::ipc::{self, Change};
+use crossterm::{
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{
+    backend::{Backend, CrosstermBackend},
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
+    Frame, Terminal,
+};
+use graphics::Protocol;
+use image::RgbaImage;
+use std::{collections::HashMap, error::Error, io::{self, Write}, sync::mpsc::Receiver, time::{Duration, SystemTime}};
+use chrono::Local;
+
+struct App {
+    storage: ClipboardStorage,
+    state: ListState,
+    changes: Receiver<Change>,
+    // Search box contents and whether keystrokes go to it
+    query: String,
+    searching: bool,
+    // Rows shown in the list, in display order
+    visible: Vec<SearchHit>,
+    // Error from the last action, shown until the next key press
+    status: Option<String>,
+    // Dimensions and file size of image items by id; None if unreadable
+    image_info: HashMap<String, Option<ImageMeta>>,
+    // How image previews are drawn
+    graphics: Protocol,
+    // The selected image, decoded; None inside if it can't be read
+    preview: Option<(String, Option<Preview>)>,
+    // Where previews come from; without it they are made from the originals
+    blobs: Option<BlobStore>,
+    // Where `ui` left room for a protocol-drawn image, and what is
+    // currently drawn there
+    image_area: Option<Rect>,
+    drawn_image: Option<(String, Rect)>,
+    // Preview pane scroll offset in rows, and the item it applies to
+    preview_scroll: u16,
+    preview_scroll_id: Option<String>,
+    // Re-read when the file changes: private mode can be toggled from the GUI too
+    config: AppConfig,
+    config_modified: Option<SystemTime>,
+}
+
+// Very long clips are cut off in the preview pane
+const MAX_PREVIEW_LINES: usize = 5000;
+const PREVIEW_PAGE: i32 = 10;
+// How long 'I' pauses recording for
+const PRIVATE_PAUSE_MINUTES: i64 = 15;
+
+struct Preview {
+    // The cached preview-sized thumbnail, so redrawing stays cheap
+    png: Vec<u8>,
+    image: RgbaImage,
+}
+
+impl Preview {
+    fn load(item: &HistoryItem, blobs: Option<&BlobStore>, encryption: &EncryptionConfig) -> Option<Self> {
+        let png = match blobs {
+            Some(blobs) => blobs.thumbnail(item, ThumbnailSize::Preview, encryption).ok()?,
+            None => item.image_data(encryption).ok()?,
+        };
+        let image = image::load_from_memory(&png).ok()?.to_rgba8();
+        Some(Self { png, image })
+    }
+}
+
+impl App {
+    fn new(storage: ClipboardStorage) -> Result<Self, Box<dyn Error>> {
+        let mut app = App {
+            storage,
+            state: ListState::default(),
+            changes: ipc::watch(),
+            query: String::new(),
+            searching: false,
+            visible: Vec::new(),
+            status: None,
+            image_info: HashMap::new(),
+            graphics: Protocol::detect(),
+            preview: None,
+            blobs: BlobStore::open().ok(),
+            image_area: None,
+            drawn_image: None,
+            preview_scroll: 0,
+            preview_scroll_id: None,
+            config_modified: AppConfig::modified(),
+            config: AppConfig::load().unwrap_or_default(),
+        };
+        app.refilter(None);
+        Ok(app)
+    }
+
+    /// History index of the highlighted row.
+    fn selected_index(&self) -> Option<usize> {
+        self.state.selected().and_then(|pos| self.visible.get(pos)).map(|hit| hit.index)
+    }
+
+    fn selected_id(&self) -> Option<String> {
+        self.selected_index().map(|i| self.storage.history[i].id().to_string())
+    }
+
+    /// Recomputes the visible rows for the current query, keeping `keep`
+    /// selected if it is still shown and the same row position otherwise.
+    fn refilter(&mut self, keep: Option<String>) {
+        let previous = self.state.selected();
+        self.visible = self.storage.search(&SearchQuery::parse(&self.query));
+        for hit in &self.visible {
+            let item = &self.storage.history[hit.index];
+            if item.kind == ClipKind::Image && !self.image_info.contains_key(item.id()) {
+                self.image_info.insert(item.id().to_string(), ImageMeta::of(item, &self.config.encryption));
+            }
+        }
+
+        let history = &self.storage.history;
+        let pos = keep
+            .and_then(|id| self.visible.iter().position(|hit| history[hit.index].id() == id))
+            .or_else(|| previous.map(|p| p.min(self.visible.len().saturating_sub(1))));
+        self.state.select(if self.visible.is_empty() { None } else { pos.or(Some(0)) });
+    }
+
+    /// Steps the `is:` filter in the query through every category and
+    /// back to none.
+    fn cycle_category(&mut self) {
+        let current = SearchQuery::parse(&self.query).category;
+        let next = match current {
+            None => Some(Category::ALL[0]),
+            Some(c) => Category::ALL.iter().skip_while(|&&other| other != c).nth(1).copied(),
+        };
+        self.set_query(SearchQuery::with_filter(&self.query, "is", next.map(|c| c.as_str())));
+    }
+
+    /// Shows only clips from the selected clip's app, or all apps again
+    /// if that filter is already on.
+    fn toggle_app_filter(&mut self) {
+        let current = SearchQuery::parse(&self.query).app;
+        let class = self.selected_index()
+            .and_then(|i| self.storage.history[i].source.as_ref())
+            .map(|source| source.class.clone());
+        let next = match (current, class) {
+            (Some(_), _) => None,
+            (None, Some(class)) => Some(class),
+            (None, None) => return,
+        };
+        self.set_query(SearchQuery::with_filter(&self.query, "app", next.as_deref()));
+    }
+
+    fn set_query(&mut self, query: String) {
+        self.query = query;
+        self.refilter(None);
+        // Jump to the best match as the query changes
+        if !self.visible.is_empty() {
+            self.state.select(Some(0));
+        }
+    }
+
+    /// Switches private mode off if it is on, and on otherwise, for
+    /// `minutes` or until switched off.
+    fn toggle_private(&mut self, minutes: Option<i64>) {
+        let on = !self.config.is_private(Local::now());
+        match AppConfig::set_private(on, minutes.map(chrono::TimeDelta::minutes)) {
+            Ok(config) => self.config = config,
+            Err(e) => self.status = Some(format!("Couldn't change private mode: {}", e)),
+        }
+    }
+
+    /// Applies changes pushed by the daemon, keeping the same item selected.
+    fn apply_changes(&mut self) {
+        let selected_id = self.selected_id();
+        let modified = AppConfig::modified();
+        if modified != self.config_modified && let Ok(config) = AppConfig::load() {
+            self.config = config;
+            self.config_modified = modified;
+        }
+
+        let mut changed = false;
+        while let Ok(change) = self.changes.try_recv() {
+            match change {
+                Change::Event(event) => self.storage.apply(&event),
+                Change::Resync => {
+                    if let Ok(storage) = ClipboardStorage::load_shared() {
+                        self.storage = storage;
+                    }
+                }
+            }
+            changed = true;
+        }
+        if changed {
+            self.refilter(selected_id);
+        }
+    }
+
+    /// Scrolls the preview pane; `render_preview` keeps it in range.
+    fn scroll_preview(&mut self, rows: i32) {
+        self.preview_scroll = (self.preview_scroll as i32 + rows).clamp(0, u16::MAX as i32) as u16;
+    }
+
+    /// The decoded preview for `id`, loading it on first use.
+    fn load_preview(&mut self, id: &str) -> Option<&Preview> {
+        if self.preview.as_ref().is_none_or(|(loaded, _)| loaded != id) {
+            let preview = self.storage.position(id).and_then(|i| Preview::load(&self.storage.history[i], self.blobs.as_ref(), &self.config.encryption));
+            self.preview = Some((id.to_string(), preview));
+        }
+        self.preview.as_ref().and_then(|(_, preview)| preview.as_ref())
+    }
+
+    fn next(&mut self) {
+        if self.visible.is_empty() {
+            return;
+        }
+        let i = match self.state.selected() {
+            Some(i) => {
+                if i >= self.visible.len() - 1 {
+                    0
+                } else {
+                    i + 1
+                }
+            }
+            None => 0,
+        };
+        self.state.select(Some(i));
+    }
+
+    fn previous(&mut self) {
+        if self.visible.is_empty() {
+            return;
+        }
+        let i = match self.state.selected() {
+            Some(i) => {
+                if i == 0 {
+                    self.visible.len() - 1
+                } else {
+                    i - 1
+                }
+            }
+            None => 0,
+        };
+        self.state.select(Some(i));
+    }
+
+    fn copy_selected(&mut self) -> anyhow::Result<()> {
+        if let Some(i) = self.selected_index()
+            && let Some(item) = self.storage.history.get(i)
+        {
+            clipboard::copy_item(item)?;
+        }
+        Ok(())
+    }
+
+    /// Copies where an image is stored rather than the picture itself.
+    // Returns whether a path was copied; only image items have one
+    fn copy_selected_path(&mut self) -> anyhow::Result<bool> {
+        let Some(item) = self.selected_index().and_then(|i| self.storage.history.get(i)) else {
+            return Ok(false);
+        };
+        if item.kind != ClipKind::Image {
+            self.status = Some("Not an image".to_string());
+            return Ok(false);
+        }
+        clipboard::copy_text(&item.content)?;
+        Ok(true)
+    }
+
+    fn toggle_pin(&mut self) {
+         if let Some(i) = self.selected_index() {
+            self.storage.toggle_pin(i);
+            // Save immediately
+            let _ = self.storage.save();
+        }
+    }
+
+    fn delete_selected(&mut self) {
+        if let Some(i) = self.selected_index() {
+            self.storage.remove(i);
+            self.refilter(None);
+            let _ = self.storage.save();
+        }
+    }
+
+    fn clear_all_unpinned(&mut self) {
+        self.storage.clear(true);
+        self.state.select(None);
+        self.refilter(None);
+        let _ = self.storage.save();
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    clipboard::run_helper_if_requested();
+
+    // An encrypted history has to be unlocked before there is anything to show
+    let storage = load_unlocked()?;
+
+    // Setup terminal
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+    let backend = CrosstermBackend::new(stdout);
+    let mut terminal = Terminal::new(backend)?;
+
+    // Create app
+    let app = App::new(storage)?;
+    let graphics = app.graphics;
+    let res = run_app(&mut terminal, app);
+    let _ = graphics::clear(&mut io::stdout(), graphics);
+
+    // Restore terminal
+    disable_raw_mode()?;
+    execute!(
+        terminal.backend_mut(),
+        LeaveAlternateScreen,
+        DisableMouseCapture
+    )?;
+    terminal.show_cursor()?;
+
+    if let Err(err) = res {
+        println!("{:?}", err);
+    }
+
+    Ok(())
+}
+
+/// Loads the history, asking for the passphrase while it is locked.
+fn load_unlocked() -> Result<ClipboardStorage, Box<dyn Error>> {
+    loop {
+        match ClipboardStorage::load_shared() {
+            Ok(storage) => return Ok(storage),
+            Err(e) if crypto::is_locked_error(&e) => {
+                let passphrase = match AppConfig::load().unwrap_or_default().encryption.key_source {
+                    KeySource::Passphrase => Some(prompt_passphrase("Clipboard history passphrase: ")?),
+                    KeySource::Keyring => None,
+                };
+                match crypto::unlock_session(passphrase.as_deref()) {
+                    Ok(()) => {}
+                    // Asking again won't bring the keyring back
+                    Err(e) if passphrase.is_none() => return Err(e.into()),
+                    Err(e) => eprintln!("{}", e),
+                }
+            }
+            Err(_) => return Ok(ClipboardStorage::default()),
+        }
+    }
+}
+
+/// Reads a passphrase from the terminal without echoing it.
+fn prompt_passphrase(prompt: &str) -> anyhow::Result<String> {
+    eprint!("{}", prompt);
+    io::stderr().flush()?;
+    enable_raw_mode()?;
+    let result = read_hidden_line();
+    disable_raw_mode()?;
+    eprintln!();
+    result
+}
+
+fn read_hidden_line() -> anyhow::Result<String> {
+    let mut line = String::new();
+    loop {
+        let Event::Key(key) = event::read()? else { continue };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Enter => return Ok(line),
+            KeyCode::Esc => anyhow::bail!("Cancelled"),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => anyhow::bail!("Cancelled"),
+            KeyCode::Backspace => {
+                line.pop();
+            }
+            KeyCode::Char(c) => line.push(c),
+            _ => {}
+        }
+    }
+}
+
+fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<()> {
+    loop {
+        app.apply_changes();
+        terminal.draw(|f| ui(f, &mut app))?;
+        sync_image(terminal, &mut app)?;
+
+        // Wake up periodically so changes from the daemon show up
+        if !event::poll(Duration::from_millis(250))? {
+            continue;
+        }
+        if let Event::Key(key) = event::read()? {
+            app.status = None;
+            // While the search box has focus, typing edits the query
+            if app.searching {
+                match key.code {
+                    KeyCode::Esc => {
+                        app.searching = false;
+                        app.set_query(String::new());
+                    }
+                    KeyCode::Enter => app.searching = false,
+                    KeyCode::Backspace => {
+                        let mut query = app.query.clone();
+                        query.pop();
+                        app.set_query(query);
+                    }
+                    KeyCode::Down => app.next(),
+                    KeyCode::Up => app.previous(),
+                    KeyCode::PageDown => app.scroll_preview(PREVIEW_PAGE),
+                    KeyCode::PageUp => app.scroll_preview(-PREVIEW_PAGE),
+                    KeyCode::Char(c) => {
+                        let query = format!("{}{}", app.query, c);
+                        app.set_query(query);
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+
+            match key.code {
+                // Esc drops an active filter before it quits
+                KeyCode::Esc if !app.query.is_empty() => app.set_query(String::new()),
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char('/') => app.searching = true,
+                KeyCode::Down | KeyCode::Char('j') => app.next(),
+                KeyCode::Up | KeyCode::Char('k') => app.previous(),
+                KeyCode::Char('J') => app.scroll_preview(1),
+                KeyCode::Char('K') => app.scroll_preview(-1),
+                KeyCode::PageDown => app.scroll_preview(PREVIEW_PAGE),
+                KeyCode::PageUp => app.scroll_preview(-PREVIEW_PAGE),
+                KeyCode::Enter => {
+                    match app.copy_selected() {
+                        Ok(()) => return Ok(()),
+                        Err(e) => app.status = Some(format!("Copy failed: {:#}", e)),
+                    }
+                }
+                KeyCode::Char('y') => match app.copy_selected_path() {
+                    Ok(true) => return Ok(()),
+                    Ok(false) => {}
+                    Err(e) => app.status = Some(format!("Copy failed: {:#}", e)),
+                },
+                KeyCode::Char('p') => app.toggle_pin(),
+                KeyCode::Char('d') | KeyCode::Delete => app.delete_selected(),
+                KeyCode::Char('c') => app.clear_all_unpinned(),
+                KeyCode::Char('t') => app.cycle_category(),
+                KeyCode::Char('a') => app.toggle_app_filter(),
+                KeyCode::Char('i') => app.toggle_private(None),
+                KeyCode::Char('I') => app.toggle_private(Some(PRIVATE_PAUSE_MINUTES)),
+                // Add Win+V equivalent? No, the OS handles the trigger.
+                _ => {}
+            }
+        }
+    }
+}
+
+fn render_preview(f: &mut Frame, app: &mut App, index: usize, area: Rect) {
+    let item = &app.storage.history[index];
+    let id = item.id().to_string();
+    if app.preview_scroll_id.as_deref() != Some(id.as_str()) {
+        app.preview_scroll = 0;
+        app.preview_scroll_id = Some(id.clone());
+    }
+
+    let mut details = Vec::new();
+    if item.kind == ClipKind::Image {
+        if let Some(Some(info)) = app.image_info.get(&id) {
+            details.push(format!("{}x{}, {}", info.width, info.height, human_size(info.bytes)));
+        }
+    } else {
+        let text = &item.content;
+        details.push(format!(
+            "{} lines, {} chars, {} bytes",
+            text.lines().count(), text.chars().count(), text.len()
+        ));
+        if let Some(category) = item.category {
+            details.push(category.label().to_string());
+        }
+        if let Some(language) = item.language {
+            details.push(language.to_string());
+        }
+    }
+    if let Some(source) = &item.source {
+        match &source.title {
+            Some(title) => details.push(format!("from {} ({})", source.class, title)),
+            None => details.push(format!("from {}", source.class)),
+        }
+    }
+    details.push(item.timestamp.format("%Y-%m-%d %H:%M:%S").to_string());
+    if item.pinned {
+        details.push("pinned".to_string());
+    } else if let Some(expires_at) = item.expires_at {
+        details.push(format!("expires {}", expires_at.format("%H:%M")));
+    }
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title("Preview")
+        .title_bottom(Line::from(details.join(" | ")).style(Style::default().fg(Color::DarkGray)));
+
+    if item.kind == ClipKind::Image {
+        render_image_preview(f, app, &id, block, area);
+        return;
+    }
+
+    let total_lines = item.content.lines().count();
+    let gutter = total_lines.max(1).to_string().len();
+    let inner = block.inner(area);
+    let source_lines: Vec<Vec<(&str, Token)>> = match item.language {
+        Some(language) => highlight::highlight_lines(&item.content, language),
+        None => item.content.lines().map(|line| vec![(line, Token::Plain)]).collect(),
+    };
+    let mut rows = 0;
+    let mut lines: Vec<Line> = source_lines
+        .into_iter()
+        .take(MAX_PREVIEW_LINES)
+        .enumerate()
+        .map(|(n, pieces)| {
+            let mut spans = vec![
+                Span::styled(format!("{:>gutter$} │ ", n + 1), Style::default().fg(Color::DarkGray)),
+            ];
+            let mut width = gutter + 3;
+            for (piece, token) in pieces {
+                // Tabs would otherwise be drawn as a single cell
+                let piece = piece.replace('\t', "    ");
+                width += piece.chars().count();
+                spans.push(Span::styled(piece, token_style(token)));
+            }
+            rows += width.div_ceil(inner.width.max(1) as usize).max(1);
+            Line::from(spans)
+        })
+        .collect();
+    if total_lines > MAX_PREVIEW_LINES {
+        lines.push(Line::styled(
+            format!("... {} more lines", total_lines - MAX_PREVIEW_LINES),
+            Style::default().fg(Color::DarkGray),
+        ));
+        rows += 1;
+    }
+
+    let max_scroll = rows.saturating_sub(inner.height as usize);
+    app.preview_scroll = app.preview_scroll.min(max_scroll as u16);
+    let preview = Paragraph::new(lines)
+        .block(block)
+        .wrap(Wrap { trim: false })
+        .scroll((app.preview_scroll, 0));
+    f.render_widget(preview, area);
+}
+
+/// Marker in front of a categorised row: a swatch for colours, a short
+/// tag for everything else.
+fn category_tag(category: Category, content: &str) -> Span<'static> {
+    let style = Style::default().add_modifier(Modifier::BOLD);
+    match category {
+        Category::Color => {
+            let [r, g, b] = category::parse_color(content).unwrap_or_default();
+            Span::styled("■ ", style.fg(Color::Rgb(r, g, b)))
+        }
+        Category::Url => Span::styled("[URL] ", style.fg(Color::Blue)),
+        Category::Email => Span::styled("[MAIL] ", style.fg(Color::Blue)),
+        Category::Path => Span::styled("[PATH] ", style.fg(Color::Green)),
+        Category::Json => Span::styled("[JSON] ", style.fg(Color::Cyan)),
+        Category::Number => Span::styled("[NUM] ", style.fg(Color::Yellow)),
+        Category::Phone => Span::styled("[TEL] ", style.fg(Color::Yellow)),
+        Category::Code => Span::styled("[CODE] ", style.fg(Color::Cyan)),
+    }
+}
+
+fn token_style(token: Token) -> Style {
+    let style = Style::default();
+    match token {
+        Token::Plain => style,
+        Token::Keyword => style.fg(Color::Magenta),
+        Token::String => style.fg(Color::Green),
+        Token::Number => style.fg(Color::Yellow),
+        Token::Comment => style.fg(Color::DarkGray).add_modifier(Modifier::ITALIC),
+        Token::Key => style.fg(Color::Cyan),
+        Token::Tag => style.fg(Color::Blue),
+    }
+}
+
+fn render_image_preview(f: &mut Frame, app: &mut App, id: &str, block: Block, area: Rect) {
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let graphics = app.graphics;
+    match app.load_preview(id) {
+        None => {
+            let message = Paragraph::new("Can't read this image").style(Style::default().fg(Color::Red));
+            f.render_widget(message, inner);
+        }
+        Some(preview) if graphics == Protocol::HalfBlocks => {
+            let lines = graphics::half_blocks(&preview.image, inner.width, inner.height);
+            f.render_widget(Paragraph::new(lines), inner);
+        }
+        // Drawn after the frame by `sync_image`
+        Some(_) => app.image_area = Some(inner),
+    }
+}
+
+/// Draws the selected image with the terminal's graphics protocol when
+/// `ui` asked for one, replacing whatever was drawn before.
+fn sync_image<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> io::Result<()> {
+    let wanted = app.image_area.zip(app.preview.as_ref().map(|(id, _)| id.clone()))
+        .map(|(area, id)| (id, area));
+    if wanted == app.drawn_image {
+        return Ok(());
+    }
+
+    let mut out = io::stdout();
+    if app.drawn_image.take().is_some() {
+        // Sixel/iTerm2 images live in the cells; repaint them all
+        graphics::clear(&mut out, app.graphics)?;
+        terminal.clear()?;
+        terminal.draw(|f| ui(f, app))?;
+    }
+    if let Some((id, area)) = &wanted
+        && let Some((_, Some(preview))) = &app.preview
+    {
+        graphics::draw(&mut out, app.graphics, &preview.png, &preview.image, *area)?;
+        app.drawn_image = Some((id.clone(), *area));
+    }
+    Ok(())
+}
+
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// The line to show for an item: the first one, or the one holding the
+/// first search match, with match positions relative to that line.
+fn display_line<'a>(item: &'a HistoryItem, hit: &SearchHit) -> (&'a str, Vec<usize>) {
+    let first_match = hit.positions.first().copied().unwrap_or(0);
+    let mut line_start = 0;
+    for line in item.content.split('\n') {
+        let len = line.chars().count();
+        if first_match < line_start + len + 1 {
+            let positions = hit.positions.iter()
+                .filter(|&&p| p >= line_start && p < line_start + len)
+                .map(|&p| p - line_start)
+                .collect();
+            return (line, positions);
+        }
+        line_start += len + 1;
+    }
+    (item.content.lines().next().unwrap_or(""), Vec::new())
+}
+
+fn ui(f: &mut Frame, app: &mut App) {
+    let size = f.area();
+    // Search box only takes space while it is in use
+    let show_search = app.searching || !app.query.is_empty();
+    let search_height = if show_search { 3 } else { 0 };
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(search_height), Constraint::Min(0), Constraint::Length(4)].as_ref())
+        .split(size);
+
+    if show_search {
+        let search_style = if app.searching {
+            Style::default().fg(Color::Yellow)
+        } else {
+            Style::default().fg(Color::Gray)
+        };
+        let search = Paragraph::new(format!("/{}", app.query))
+            .style(search_style)
+            .block(Block::default().borders(Borders::ALL).title("Search"));
+        f.render_widget(search, chunks[0]);
+        if app.searching {
+            // Border + the leading '/'
+            let x = chunks[0].x + 2 + app.query.chars().count() as u16;
+            f.set_cursor_position((x.min(chunks[0].right().saturating_sub(2)), chunks[0].y + 1));
+        }
+    }
+
+    let items: Vec<ListItem> = app
+        .visible
+        .iter()
+        .map(|hit| (&app.storage.history[hit.index], hit))
+        .map(|(i, hit)| {
+            let (content, positions) = display_line(i, hit);
+            let pinned = if i.pinned { " [PIN]" } else { "" };
+            let time = i.timestamp.format("%H:%M");
+            let style = if i.pinned {
+                 Style::default().fg(Color::Yellow)
+            } else {
+                 Style::default()
+            };
+            let mut spans = vec![Span::styled(format!("{} ", time), Style::default().fg(Color::DarkGray))];
+            if i.kind == ClipKind::Image {
+                let description = match app.image_info.get(i.id()) {
+                    Some(Some(info)) => format!("{}x{}, {}", info.width, info.height, human_size(info.bytes)),
+                    _ => "file missing".to_string(),
+                };
+                spans.push(Span::styled("[IMG] ", Style::default().fg(Color::Magenta).add_modifier(Modifier::BOLD)));
+                spans.push(Span::styled(description, style.fg(Color::Magenta)));
+                spans.push(Span::styled(pinned, Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)));
+                return ListItem::new(Line::from(spans));
+            }
+            if let Some(category) = i.category {
+                spans.push(category_tag(category, &i.content));
+            }
+            for (run, matched) in highlight_runs(content, &positions) {
+                let run_style = if matched {
+                    style.fg(Color::Cyan).add_modifier(Modifier::BOLD | Modifier::UNDERLINED)
+                } else {
+                    style
+                };
+                spans.push(Span::styled(run.to_string(), run_style));
+            }
+            spans.push(Span::styled(pinned, Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)));
+            ListItem::new(Line::from(spans))
+        })
+        .collect();
+
+    let title = if app.query.is_empty() {
+        "Clipboard History".to_string()
+    } else {
+        format!("Clipboard History ({} of {})", app.visible.len(), app.storage.history.len())
+    };
+    let mut block = Block::default().borders(Borders::ALL).title(title);
+    if app.config.is_private(Local::now()) {
+        let label = match app.config.private_until {
+            Some(until) => format!(" PRIVATE until {} ", until.format("%H:%M")),
+            None => " PRIVATE ".to_string(),
+        };
+        let style = Style::default().fg(Color::Black).bg(Color::Magenta).add_modifier(Modifier::BOLD);
+        block = block.title(Line::from(label).style(style).right_aligned());
+    }
+    let list = List::new(items)
+        .block(block)
+        .highlight_style(
+            Style::default()
+                .bg(Color::Blue)
+                .fg(Color::White)
+                .add_modifier(Modifier::BOLD),
+        )
+        .highlight_symbol(">> ");
+
+    // The selected entry is previewed beside the list, or below it when
+    // the terminal is too narrow for both
+    app.image_area = None;
+    match app.selected_index() {
+        Some(index) => {
+            let direction = if chunks[1].width >= 80 { Direction::Horizontal } else { Direction::Vertical };
+            let panes = Layout::default()
+                .direction(direction)
+                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .split(chunks[1]);
+            f.render_stateful_widget(list, panes[0], &mut app.state);
+            render_preview(f, app, index, panes[1]);
+        }
+        None => f.render_stateful_widget(list, chunks[1], &mut app.state),
+    }
+
+    // Short lines, so the keys still fit on an 80 column terminal
+    let help_text = if app.searching {
+        "Type to filter: 'text exact, re: regex, is:url, lang:rust, app:firefox\n\
+         Up/Down: Navigate | PgUp/PgDn: Scroll Preview | Enter: Done | Esc: Clear"
+    } else {
+        "Up/Down: Navigate | Enter: Paste | /: Search | y: Copy Image Path | Esc: Quit\n\
+         p: Pin | d: Delete | c: Clear Unpinned | t: Filter by Type | a: Filter by App\n\
+         J/K, PgUp/PgDn: Scroll Preview | i: Private Mode | I: Pause 15 min"
+    };
+    let help = match &app.status {
+        Some(status) => Paragraph::new(status.as_str()).style(Style::default().fg(Color::Red)),
+        None => Paragraph::new(help_text).style(Style::default().fg(Color::Gray)),
+    };
+    let help = help
+        .block(Block::default().borders(Borders::TOP));
+    f.render_widget(help, chunks[2]);
+}
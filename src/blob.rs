@@ -0,0 +1,244 @@
+use anyhow::{Result, Context};
+use directories::ProjectDirs;
+use image::{ImageFormat, ImageReader, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use crate::crypto::{self, EncryptionConfig};
+use crate::{ClipboardStorage, HistoryItem};
+
+/// Size and dimensions of an image clip, recorded when it is copied.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageMeta {
+    pub width: u32,
+    pub height: u32,
+    /// Size of the stored PNG, before any encryption
+    pub bytes: u64,
+}
+
+impl ImageMeta {
+    /// What was recorded for `item`, or else what its file says. Reads
+    /// just the header, not the whole image, unless the file is encrypted.
+    pub fn of(item: &HistoryItem, encryption: &EncryptionConfig) -> Option<Self> {
+        if item.image.is_some() {
+            return item.image;
+        }
+        let path = item.image_path()?;
+        if let Ok((width, height)) = image::image_dimensions(path) {
+            let bytes = fs::metadata(path).ok()?.len();
+            return Some(Self { width, height, bytes });
+        }
+        let png = item.image_data(encryption).ok()?;
+        let (width, height) = ImageReader::new(Cursor::new(&png))
+            .with_guessed_format()
+            .ok()?
+            .into_dimensions()
+            .ok()?;
+        Some(Self { width, height, bytes: png.len() as u64 })
+    }
+}
+
+/// The sizes thumbnails are cached at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThumbnailSize {
+    /// Rows of the history list
+    List,
+    /// Larger previews
+    Preview,
+}
+
+impl ThumbnailSize {
+    pub const ALL: [ThumbnailSize; 2] = [ThumbnailSize::List, ThumbnailSize::Preview];
+
+    /// Longest side in pixels. Images already this small aren't scaled.
+    pub fn pixels(&self) -> u32 {
+        match self {
+            ThumbnailSize::List => 256,
+            ThumbnailSize::Preview => 1024,
+        }
+    }
+}
+
+/// Where image clips live. Originals are stored once per content hash as
+/// `<hash>.png` in the images dir; thumbnails are a cache next to the
+/// other caches, made when an image is stored or first asked for.
+/// Encrypted stores encrypt both.
+#[derive(Debug, Clone)]
+pub struct BlobStore {
+    dir: PathBuf,
+    thumbnails: PathBuf,
+}
+
+impl BlobStore {
+    pub fn open() -> Result<Self> {
+        let proj_dirs = ProjectDirs::from("com", "asthetic", "clipboard")
+            .context("Could not determine project directories")?;
+        Ok(Self::new(ClipboardStorage::get_images_dir()?, proj_dirs.cache_dir().join("thumbnails")))
+    }
+
+    pub fn new(dir: PathBuf, thumbnails: PathBuf) -> Self {
+        Self { dir, thumbnails }
+    }
+
+    /// Directory the originals are stored in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Where the original with `hash` is stored.
+    pub fn path(&self, hash: &str) -> PathBuf {
+        self.dir.join(format!("{}.png", hash))
+    }
+
+    fn thumbnail_path(&self, hash: &str, size: ThumbnailSize) -> PathBuf {
+        self.thumbnails.join(size.pixels().to_string()).join(format!("{}.png", hash))
+    }
+
+    /// Stores `image` under `hash` as PNG along with its thumbnails.
+    /// Content already stored isn't written again. Returns the path of
+    /// the original.
+    pub fn put(&self, config: &EncryptionConfig, hash: &str, image: &RgbaImage) -> Result<(PathBuf, ImageMeta)> {
+        let path = self.path(hash);
+        if !path.is_file() {
+            crypto::write_file(config, &path, &encode(image)?)?;
+        }
+        let meta = ImageMeta { width: image.width(), height: image.height(), bytes: crypto::plaintext_len(&path)? };
+        for size in ThumbnailSize::ALL {
+            let thumbnail = self.thumbnail_path(hash, size);
+            if thumbnail.exists() {
+                continue;
+            }
+            if let Err(e) = self.write_thumbnail(config, &thumbnail, &scale_down(image, size)?) {
+                eprintln!("Failed to cache thumbnail: {}", e);
+            }
+        }
+        Ok((path, meta))
+    }
+
+    /// PNG of `item` no larger than `size`, from the cache where possible.
+    /// Missing thumbnails, like those of clips copied by older versions,
+    /// are made from the original and cached.
+    pub fn thumbnail(&self, item: &HistoryItem, size: ThumbnailSize, encryption: &EncryptionConfig) -> Result<Vec<u8>> {
+        let path = self.thumbnail_path(item.id(), size);
+        let locked = match crypto::read_file(encryption, &path) {
+            Ok(png) => return Ok(png),
+            Err(e) => crypto::is_locked_error(&e),
+        };
+        let original = image::load_from_memory(&item.image_data(encryption)?)?.to_rgba8();
+        let png = scale_down(&original, size)?;
+        // Without the key the cache can't be written in encrypted form
+        if !locked && let Err(e) = self.write_thumbnail(encryption, &path, &png) {
+            eprintln!("Failed to cache thumbnail: {}", e);
+        }
+        Ok(png)
+    }
+
+    fn write_thumbnail(&self, config: &EncryptionConfig, path: &Path, png: &[u8]) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        crypto::write_file(config, path, png)
+    }
+
+    /// Deletes an original and its thumbnails. Returns the bytes freed.
+    pub fn remove(&self, path: &Path) -> u64 {
+        let mut freed = remove_file(path);
+        if let Some(hash) = path.file_stem().and_then(|stem| stem.to_str()) {
+            for size in ThumbnailSize::ALL {
+                freed += remove_file(&self.thumbnail_path(hash, size));
+            }
+        }
+        freed
+    }
+
+    /// Deletes cached thumbnails of images other than `keep`, by hash.
+    /// Returns the bytes freed.
+    pub fn prune_thumbnails(&self, keep: &HashSet<&str>) -> u64 {
+        let mut freed = 0;
+        for size in ThumbnailSize::ALL {
+            let Ok(entries) = fs::read_dir(self.thumbnails.join(size.pixels().to_string())) else {
+                continue;
+            };
+            for path in entries.flatten().map(|entry| entry.path()) {
+                let hash = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+                if !keep.contains(hash) {
+                    freed += remove_file(&path);
+                }
+            }
+        }
+        freed
+    }
+
+    /// Drops the whole thumbnail cache, e.g. after the store was encrypted
+    /// or decrypted. It is refilled on demand.
+    pub fn clear_thumbnails(&self) -> Result<()> {
+        match fs::remove_dir_all(&self.thumbnails) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+// PNG of `image` fitted into a square of `size`, keeping its aspect
+// ratio. Smaller images aren't scaled up.
+fn scale_down(image: &RgbaImage, size: ThumbnailSize) -> Result<Vec<u8>> {
+    let (width, height) = image.dimensions();
+    let longest = width.max(height);
+    if longest <= size.pixels() {
+        return encode(image);
+    }
+    let scale = |side: u32| ((side as u64 * size.pixels() as u64 / longest as u64) as u32).max(1);
+    encode(&image::imageops::thumbnail(image, scale(width), scale(height)))
+}
+
+fn encode(image: &RgbaImage) -> Result<Vec<u8>> {
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(png)
+}
+
+// Size of the file removed, 0 if there was none
+fn remove_file(path: &Path) -> u64 {
+    let len = fs::metadata(path).map_or(0, |m| m.len());
+    match fs::remove_file(path) {
+        Ok(()) => len,
+        Err(_) => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ClipKind;
+
+    #[test]
+    fn test_put_and_thumbnail() {
+        let root = crate::test_dir("blobs");
+        let store = BlobStore::new(root.join("images"), root.join("thumbnails"));
+        fs::create_dir_all(store.dir()).unwrap();
+        let config = EncryptionConfig::default();
+
+        let image = RgbaImage::from_pixel(2048, 512, image::Rgba([200, 30, 30, 255]));
+        let (path, meta) = store.put(&config, "abc", &image).unwrap();
+        assert_eq!(path, store.path("abc"));
+        assert_eq!((meta.width, meta.height), (2048, 512));
+        assert_eq!(meta.bytes, fs::metadata(&path).unwrap().len());
+        // The same content again is not written twice
+        assert_eq!(store.put(&config, "abc", &image).unwrap().1, meta);
+
+        let mut storage = ClipboardStorage::default();
+        storage.add(path.to_string_lossy().to_string(), ClipKind::Image, Some("abc".to_string()), 50);
+        let thumbnail = store.thumbnail(&storage.history[0], ThumbnailSize::List, &config).unwrap();
+        let dimensions = image::load_from_memory(&thumbnail).unwrap();
+        assert_eq!((dimensions.width(), dimensions.height()), (256, 64));
+
+        assert!(store.remove(&path) > 0);
+        assert!(!path.exists());
+        assert!(!store.thumbnail_path("abc", ThumbnailSize::List).exists());
+
+        let _ = fs::remove_dir_all(&root);
+    }
+}
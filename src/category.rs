@@ -0,0 +1,234 @@
+use serde::{Deserialize, Serialize};
+
+use crate::highlight::Language;
+
+/// What a text clip looks like, so it can get a fitting icon and be
+/// filtered on.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    Url,
+    Email,
+    Path,
+    /// Hex (`#a0b1c2`) or CSS `rgb()` colour
+    Color,
+    Json,
+    Number,
+    Phone,
+    /// Source code in any language `highlight::detect` knows, except JSON
+    Code,
+}
+
+impl Category {
+    pub const ALL: [Category; 8] = [
+        Category::Url, Category::Email, Category::Path, Category::Color, Category::Json,
+        Category::Number, Category::Phone, Category::Code,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Category::Url => "url",
+            Category::Email => "email",
+            Category::Path => "path",
+            Category::Color => "color",
+            Category::Json => "json",
+            Category::Number => "number",
+            Category::Phone => "phone",
+            Category::Code => "code",
+        }
+    }
+
+    /// Name for display, e.g. in tooltips.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Category::Url => "URL",
+            Category::Email => "Email",
+            Category::Path => "Path",
+            Category::Color => "Color",
+            Category::Json => "JSON",
+            Category::Number => "Number",
+            Category::Phone => "Phone",
+            Category::Code => "Code",
+        }
+    }
+
+    /// Parses a name typed by the user. Plurals and a few synonyms work too.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        let singular = name.strip_suffix('s').unwrap_or(&name);
+        let canonical = match singular {
+            "link" => "url",
+            "colour" => "color",
+            "mail" => "email",
+            other => other,
+        };
+        Self::ALL.into_iter().find(|c| c.as_str() == canonical)
+    }
+}
+
+impl std::fmt::Display for Category {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Works out the category of a text clip. `language` is what
+/// `highlight::detect` found for it; single-line forms like URLs take
+/// precedence over it.
+pub fn classify(content: &str, language: Option<Language>) -> Option<Category> {
+    let text = content.trim();
+    if text.is_empty() {
+        return None;
+    }
+    if !text.contains('\n') {
+        if is_url(text) {
+            return Some(Category::Url);
+        }
+        if is_email(text) {
+            return Some(Category::Email);
+        }
+        if parse_color(text).is_some() {
+            return Some(Category::Color);
+        }
+        if is_path(text) {
+            return Some(Category::Path);
+        }
+        if is_number(text) {
+            return Some(Category::Number);
+        }
+        if is_phone(text) {
+            return Some(Category::Phone);
+        }
+    }
+    match language {
+        Some(Language::Json) => Some(Category::Json),
+        Some(_) => Some(Category::Code),
+        None => None,
+    }
+}
+
+/// RGB value of a colour written as `#rgb`, `#rgba`, `#rrggbb`,
+/// `#rrggbbaa`, `rgb(r, g, b)` or `rgba(r, g, b, a)`. Alpha is ignored.
+pub fn parse_color(text: &str) -> Option<[u8; 3]> {
+    let text = text.trim();
+    if let Some(hex) = text.strip_prefix('#') {
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let channel = |i: usize, width: usize| -> Option<u8> {
+            let value = u8::from_str_radix(&hex[i * width..(i + 1) * width], 16).ok()?;
+            // Short forms repeat each digit: #abc is #aabbcc
+            Some(if width == 1 { value * 17 } else { value })
+        };
+        let width = match hex.len() {
+            3 | 4 => 1,
+            6 | 8 => 2,
+            _ => return None,
+        };
+        return Some([channel(0, width)?, channel(1, width)?, channel(2, width)?]);
+    }
+
+    let lower = text.to_lowercase();
+    let args = lower
+        .strip_prefix("rgba(")
+        .or_else(|| lower.strip_prefix("rgb("))?
+        .strip_suffix(')')?;
+    let parts: Vec<&str> = args.split(',').map(str::trim).collect();
+    if parts.len() != 3 && parts.len() != 4 {
+        return None;
+    }
+    let mut rgb = [0; 3];
+    for (value, part) in rgb.iter_mut().zip(&parts) {
+        *value = part.parse().ok()?;
+    }
+    Some(rgb)
+}
+
+fn is_url(text: &str) -> bool {
+    if text.contains(char::is_whitespace) {
+        return false;
+    }
+    if let Some((scheme, rest)) = text.split_once("://") {
+        return !scheme.is_empty()
+            && scheme.chars().all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+            && !rest.is_empty();
+    }
+    text.strip_prefix("www.").is_some_and(|host| host.contains('.'))
+}
+
+fn is_email(text: &str) -> bool {
+    let text = text.strip_prefix("mailto:").unwrap_or(text);
+    let Some((local, domain)) = text.split_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && local.chars().all(|c| c.is_alphanumeric() || "._%+-".contains(c))
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && domain.chars().all(|c| c.is_alphanumeric() || c == '.' || c == '-')
+}
+
+fn is_path(text: &str) -> bool {
+    let unix = ["/", "~/", "./", "../"].iter().any(|prefix| text.starts_with(prefix));
+    let windows = text.len() > 2
+        && text.as_bytes()[0].is_ascii_alphabetic()
+        && (text[1..].starts_with(":\\") || text[1..].starts_with(":/"));
+    // `/ note` or `// comment` aren't paths
+    (unix || windows)
+        && text.len() > 1
+        && !text.starts_with("//")
+        && !text.chars().nth(1).is_some_and(char::is_whitespace)
+}
+
+fn is_number(text: &str) -> bool {
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        return u64::from_str_radix(&hex.replace('_', ""), 16).is_ok();
+    }
+    // Thousands separators as people write them
+    let plain = text.replace(['_', ','], "");
+    plain.chars().any(|c| c.is_ascii_digit())
+        && plain.chars().all(|c| c.is_ascii_digit() || "+-.eE".contains(c))
+        && plain.parse::<f64>().is_ok()
+}
+
+fn is_phone(text: &str) -> bool {
+    if !text.chars().all(|c| c.is_ascii_digit() || " +-()".contains(c)) {
+        return false;
+    }
+    let digits = text.chars().filter(char::is_ascii_digit).count();
+    // Separated digit groups alone could be a date or an ID, so without a
+    // country code it takes a full-length number
+    let min_digits = if text.starts_with('+') { 7 } else { 10 };
+    (min_digits..=15).contains(&digits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let plain = |text: &str| classify(text, crate::highlight::detect(text));
+        assert_eq!(plain("https://example.com/a?b=c"), Some(Category::Url));
+        assert_eq!(plain("www.example.com"), Some(Category::Url));
+        assert_eq!(plain("me@example.org"), Some(Category::Email));
+        assert_eq!(plain("~/Documents/notes.txt"), Some(Category::Path));
+        assert_eq!(plain("1,234.5"), Some(Category::Number));
+        assert_eq!(plain("+44 20 7946 0958"), Some(Category::Phone));
+        assert_eq!(plain("2024-01-01"), None);
+        assert_eq!(plain(r#"{"a": 1}"#), Some(Category::Json));
+        assert_eq!(plain("git log --oneline"), Some(Category::Code));
+        assert_eq!(plain("Call me maybe"), None);
+
+        assert_eq!(parse_color("#0af"), Some([0x00, 0xaa, 0xff]));
+        assert_eq!(parse_color("#336699cc"), Some([0x33, 0x66, 0x99]));
+        assert_eq!(parse_color("rgba(10, 20, 30, 0.5)"), Some([10, 20, 30]));
+        assert_eq!(parse_color("#12345"), None);
+        assert_eq!(plain("#ff0000"), Some(Category::Color));
+
+        assert_eq!(Category::from_name("URLs"), Some(Category::Url));
+        assert_eq!(Category::from_name("colour"), Some(Category::Color));
+        assert_eq!(Category::from_name("nope"), None);
+    }
+}
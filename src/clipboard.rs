@@ -0,0 +1,355 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use anyhow::{Result, Context, bail};
+use arboard::{Clipboard, ImageData, SetExtLinux};
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::protocol::Event;
+use x11rb::protocol::xproto::{
+    Atom, AtomEnum, ChangeWindowAttributesAux, ConnectionExt as _, CreateWindowAux, EventMask,
+    PropMode, Property, SelectionNotifyEvent, Window, WindowClass, SELECTION_NOTIFY_EVENT,
+};
+use x11rb::wrapper::ConnectionExt as _;
+use x11rb::{CURRENT_TIME, NONE};
+
+use crate::crypto::EncryptionConfig;
+use crate::ipc::DaemonClient;
+use crate::{mime, AppConfig, ClipKind, HistoryItem};
+
+// Set on the helper process `copy_item` starts
+const HELPER_ENV: &str = "ASTHETIC_CLIPBOARD_HELPER";
+
+/// Puts `item` back on the system clipboard from a front-end.
+///
+/// Clipboard contents are served by the process that set them, so they
+/// have to come from something that outlives the window. The daemon does
+/// it when it is running; otherwise a copy of the current executable is
+/// started that holds the clipboard until something else is copied.
+/// Front-ends must call `run_helper_if_requested` first thing in `main`.
+pub fn copy_item(item: &HistoryItem) -> Result<()> {
+    if let Ok(mut client) = DaemonClient::connect()
+        && client.copy(item.id()).is_ok()
+    {
+        return Ok(());
+    }
+    spawn_helper(item)
+}
+
+/// Puts plain text that isn't a history item (e.g. an image's path) on
+/// the clipboard, through the same helper `copy_item` falls back to.
+pub fn copy_text(text: &str) -> Result<()> {
+    spawn_helper(&HistoryItem::text(text))
+}
+
+/// Puts `item` on the clipboard from this process and returns right away.
+/// The contents disappear when the process exits, so this is for the
+/// daemon; front-ends use `copy_item`. `encryption` is how the history
+/// is stored, for reading images.
+pub fn write(item: &HistoryItem, encryption: &EncryptionConfig) -> Result<()> {
+    if x11_available() {
+        match start_x11_owner(formats(item, encryption)?) {
+            Ok(_) => return Ok(()),
+            Err(e) => eprintln!("Could not own the X11 clipboard ({}), falling back to arboard", e),
+        }
+    }
+    write_with_arboard(item, encryption, false, || {})
+}
+
+/// Becomes the clipboard helper if `copy_item` started this process:
+/// reads the item from stdin, takes the clipboard, reports back on stdout
+/// and exits once another application replaces the contents.
+pub fn run_helper_if_requested() {
+    if std::env::var_os(HELPER_ENV).is_none() {
+        return;
+    }
+    let code = match run_helper() {
+        Ok(()) => 0,
+        Err(e) => {
+            // Only reaches the front-end if the clipboard was never taken
+            println!("{:#}", e);
+            1
+        }
+    };
+    std::process::exit(code);
+}
+
+fn run_helper() -> Result<()> {
+    let mut input = Vec::new();
+    std::io::stdin().read_to_end(&mut input)?;
+    let item: HistoryItem = serde_json::from_slice(&input)
+        .context("Clipboard helper received a malformed item")?;
+    let encryption = AppConfig::load().unwrap_or_default().encryption;
+
+    let ready = || {
+        let mut stdout = std::io::stdout();
+        let _ = writeln!(stdout, "ok");
+        let _ = stdout.flush();
+    };
+    if x11_available() {
+        match start_x11_owner(formats(&item, &encryption)?) {
+            Ok(owner) => {
+                ready();
+                let _ = owner.join();
+                return Ok(());
+            }
+            Err(e) => eprintln!("Could not own the X11 clipboard ({}), falling back to arboard", e),
+        }
+    }
+    write_with_arboard(&item, &encryption, true, ready)
+}
+
+fn spawn_helper(item: &HistoryItem) -> Result<()> {
+    let mut child = Command::new(std::env::current_exe()?)
+        .env(HELPER_ENV, "1")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        // Own process group, so closing the TUI's terminal doesn't kill it
+        .process_group(0)
+        .spawn()
+        .context("Failed to start clipboard helper")?;
+
+    let mut stdin = child.stdin.take().context("Clipboard helper has no stdin")?;
+    serde_json::to_writer(&mut stdin, item)?;
+    drop(stdin);
+
+    let stdout = child.stdout.take().context("Clipboard helper has no stdout")?;
+    let mut line = String::new();
+    BufReader::new(stdout).read_line(&mut line)?;
+    // Reap it whenever it finishes so it doesn't linger as a zombie
+    thread::spawn(move || child.wait());
+
+    match line.trim_end() {
+        "ok" => Ok(()),
+        "" => bail!("Clipboard helper exited without taking the clipboard"),
+        error => bail!("{}", error),
+    }
+}
+
+fn x11_available() -> bool {
+    std::env::var_os("DISPLAY").is_some()
+}
+
+/// Every X11 target `item` is offered under, with its bytes.
+fn formats(item: &HistoryItem, encryption: &EncryptionConfig) -> Result<Vec<(String, Vec<u8>)>> {
+    if item.kind == ClipKind::Image {
+        // Images are stored as PNG, which is what pasting apps ask for
+        let png = item.image_data(encryption)
+            .with_context(|| format!("Image {} is no longer available", item.content))?;
+        return Ok(vec![("image/png".to_string(), png)]);
+    }
+    let mut formats: Vec<(String, Vec<u8>)> = mime::TEXT_TARGETS
+        .iter()
+        .map(|target| (target.to_string(), item.content.clone().into_bytes()))
+        .collect();
+    formats.extend(
+        item.representations
+            .iter()
+            .map(|(mime, data)| (mime.clone(), data.clone().into_bytes())),
+    );
+    Ok(formats)
+}
+
+/// Fallback when there is no X server to talk to directly. arboard can
+/// only offer HTML with its plain-text alternative, a file list, plain
+/// text or an image. With `wait` it blocks until the contents are
+/// replaced; `ready` runs once everything is loaded.
+fn write_with_arboard(item: &HistoryItem, encryption: &EncryptionConfig, wait: bool, ready: impl FnOnce()) -> Result<()> {
+    let mut clipboard = Clipboard::new()?;
+    let image = match item.kind {
+        ClipKind::Image => {
+            let png = item.image_data(encryption)
+                .with_context(|| format!("Image {} is no longer available", item.content))?;
+            let img = image::load_from_memory(&png)?.to_rgba8();
+            Some(ImageData {
+                width: img.width() as usize,
+                height: img.height() as usize,
+                bytes: img.into_raw().into(),
+            })
+        }
+        _ => None,
+    };
+    ready();
+
+    let set = if wait { clipboard.set().wait() } else { clipboard.set() };
+    if let Some(image) = image {
+        set.image(image)?;
+    } else if let Some(html) = item.representations.get(mime::TEXT_HTML) {
+        set.html(html, Some(&item.content))?;
+    } else if item.kind == ClipKind::Files {
+        set.file_list(&item.file_paths())?;
+    } else {
+        set.text(&item.content)?;
+    }
+    Ok(())
+}
+
+/// Takes the X11 CLIPBOARD selection and serves `formats` from a
+/// background thread, which finishes when another client takes over.
+/// Returns once the selection is ours.
+fn start_x11_owner(formats: Vec<(String, Vec<u8>)>) -> Result<JoinHandle<()>> {
+    let (tx, rx) = mpsc::channel();
+    let owner = thread::spawn(move || {
+        if let Err(e) = serve_x11(&formats, &tx) {
+            // Only reaches the caller if ownership was never established
+            if tx.send(Err(e)).is_err() {
+                eprintln!("X11 clipboard owner stopped");
+            }
+        }
+    });
+    rx.recv_timeout(Duration::from_secs(5))
+        .context("Timed out taking the X11 clipboard")??;
+    Ok(owner)
+}
+
+fn serve_x11(formats: &[(String, Vec<u8>)], ready: &Sender<Result<()>>) -> Result<()> {
+    let (conn, screen_num) = x11rb::connect(None)
+        .context("Failed to connect to the X server")?;
+    let root = conn.setup().roots[screen_num].root;
+    let win = conn.generate_id()?;
+    conn.create_window(
+        0, win, root, 0, 0, 1, 1, 0,
+        WindowClass::INPUT_OUTPUT, 0, &CreateWindowAux::new(),
+    )?;
+
+    let intern = |name: &str| -> Result<Atom> {
+        Ok(conn.intern_atom(false, name.as_bytes())?.reply()?.atom)
+    };
+    let clipboard = intern("CLIPBOARD")?;
+    let targets = intern("TARGETS")?;
+    let mut offered: Vec<(Atom, &[u8])> = Vec::new();
+    for (name, data) in formats {
+        offered.push((intern(name)?, data));
+    }
+
+    conn.set_selection_owner(win, clipboard, CURRENT_TIME)?;
+    if conn.get_selection_owner(clipboard)?.reply()?.owner != win {
+        bail!("Another client kept the clipboard");
+    }
+    conn.flush()?;
+    let _ = ready.send(Ok(()));
+
+    // Anything larger goes out in chunks of this size with INCR
+    let max_bytes = conn.maximum_request_bytes().saturating_sub(64);
+    let incr = intern("INCR")?;
+    let mut transfers: Vec<IncrTransfer> = Vec::new();
+    loop {
+        match conn.wait_for_event()? {
+            Event::SelectionRequest(req) => {
+                // Obsolete clients leave the property unset
+                let property = if req.property == NONE { req.target } else { req.property };
+                let served = if req.target == targets {
+                    let mut atoms: Vec<Atom> = offered.iter().map(|(atom, _)| *atom).collect();
+                    atoms.push(targets);
+                    conn.change_property32(PropMode::REPLACE, req.requestor, property, AtomEnum::ATOM, &atoms)?;
+                    true
+                } else if let Some((_, data)) = offered.iter().find(|(atom, _)| *atom == req.target) {
+                    if data.len() <= max_bytes {
+                        conn.change_property8(PropMode::REPLACE, req.requestor, property, req.target, data)?;
+                    } else {
+                        // The requestor deleting the property asks for the
+                        // next chunk, so watch for that before announcing
+                        let watch = ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE);
+                        conn.change_window_attributes(req.requestor, &watch)?;
+                        let size = u32::try_from(data.len()).unwrap_or(u32::MAX);
+                        conn.change_property32(PropMode::REPLACE, req.requestor, property, incr, &[size])?;
+                        transfers.retain(|t| (t.requestor, t.property) != (req.requestor, property));
+                        transfers.push(IncrTransfer::new(req.requestor, property, req.target, data));
+                    }
+                    true
+                } else {
+                    false
+                };
+
+                let notify = SelectionNotifyEvent {
+                    response_type: SELECTION_NOTIFY_EVENT,
+                    sequence: 0,
+                    time: req.time,
+                    requestor: req.requestor,
+                    selection: req.selection,
+                    target: req.target,
+                    property: if served { property } else { NONE },
+                };
+                conn.send_event(false, req.requestor, EventMask::NO_EVENT, notify)?;
+                conn.flush()?;
+            }
+            Event::PropertyNotify(event) if event.state == Property::DELETE => {
+                let Some(i) = transfers.iter().position(|t| (t.requestor, t.property) == (event.window, event.atom)) else {
+                    continue;
+                };
+                let target = transfers[i].target;
+                match transfers[i].next_chunk(max_bytes) {
+                    Some(chunk) => {
+                        conn.change_property8(PropMode::REPLACE, event.window, event.atom, target, chunk)?;
+                    }
+                    None => {
+                        transfers.remove(i);
+                        if !transfers.iter().any(|t| t.requestor == event.window) {
+                            let unwatch = ChangeWindowAttributesAux::new().event_mask(EventMask::NO_EVENT);
+                            conn.change_window_attributes(event.window, &unwatch)?;
+                        }
+                    }
+                }
+                conn.flush()?;
+            }
+            // Someone else copied something
+            Event::SelectionClear(_) => return Ok(()),
+            _ => {}
+        }
+    }
+}
+
+// A target too large for one request, sent with the INCR protocol: each
+// time the requestor deletes the property the next chunk goes in, and an
+// empty chunk marks the end.
+struct IncrTransfer<'a> {
+    requestor: Window,
+    property: Atom,
+    target: Atom,
+    data: &'a [u8],
+    sent: usize,
+    finished: bool,
+}
+
+impl<'a> IncrTransfer<'a> {
+    fn new(requestor: Window, property: Atom, target: Atom, data: &'a [u8]) -> Self {
+        Self { requestor, property, target, data, sent: 0, finished: false }
+    }
+
+    // The next chunk of at most `max` bytes, or `None` once the closing
+    // empty one was handed out
+    fn next_chunk(&mut self, max: usize) -> Option<&'a [u8]> {
+        if self.finished {
+            return None;
+        }
+        let chunk = &self.data[self.sent..(self.sent + max).min(self.data.len())];
+        self.sent += chunk.len();
+        self.finished = chunk.is_empty();
+        Some(chunk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_incr_chunks() {
+        // A screenshot-sized payload, well over the request limit
+        let max = 256 * 1024;
+        let data: Vec<u8> = (0..3 * max + 5).map(|i| i as u8).collect();
+        let mut transfer = IncrTransfer::new(1, 2, 3, &data);
+
+        let mut chunks = Vec::new();
+        while let Some(chunk) = transfer.next_chunk(max) {
+            chunks.push(chunk);
+        }
+        let sizes: Vec<usize> = chunks.iter().map(|chunk| chunk.len()).collect();
+        assert_eq!(sizes, vec![max, max, max, 5, 0]);
+        assert_eq!(chunks.concat(), data);
+        assert_eq!(transfer.next_chunk(max), None);
+    }
+}
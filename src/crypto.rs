@@ -0,0 +1,372 @@
+use anyhow::{Result, Context, bail};
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use zeroize::Zeroizing;
+
+use crate::backend::{self, HistoryBackend, JsonBackend};
+use crate::blob::BlobStore;
+use crate::{ipc, mime, AppConfig, BackendKind, ClipKind, ClipboardStorage};
+
+// Sealed data: MAGIC, a random nonce, then the XChaCha20-Poly1305
+// ciphertext and tag. The trailing byte is the format version.
+const MAGIC: &[u8] = b"ACLIPENC\x01";
+const NONCE_LEN: usize = 24;
+const TAG_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+// Sealed into the passphrase file so a wrong passphrase is caught up front
+const CHECK_PLAINTEXT: &[u8] = b"asthetic-clipboard";
+// How the key is found again in the Secret Service
+const KEYRING_ATTRIBUTES: [&str; 4] = ["service", "asthetic-clipboard", "key", "storage"];
+
+type Key = Zeroizing<[u8; KEY_LEN]>;
+
+/// Why `daemon encrypt` refuses the sqlite and journal backends.
+pub const UNSUPPORTED_BACKEND: &str =
+    "Encryption is only supported by the json storage backend; set \"storage_backend\" to \"json\" first";
+
+// The unlocked key, for everything this process reads and writes
+static KEY: Mutex<Option<Key>> = Mutex::new(None);
+
+/// Where the encryption key comes from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum KeySource {
+    /// A random key kept in the Secret Service (GNOME Keyring, KWallet)
+    #[default]
+    Keyring,
+    /// Derived from a passphrase the user types to unlock
+    Passphrase,
+}
+
+/// Encryption at rest for `history.json` and the image files. Turned on
+/// and off with `daemon encrypt` / `daemon decrypt`, which rewrite the
+/// existing store, rather than by editing the config.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct EncryptionConfig {
+    pub enabled: bool,
+    pub key_source: KeySource,
+}
+
+/// Error for sealed data touched before the key is available. Front-ends
+/// check for it with `is_locked_error` and offer to unlock.
+#[derive(Debug)]
+pub struct Locked;
+
+impl std::fmt::Display for Locked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Clipboard history is locked")
+    }
+}
+
+impl std::error::Error for Locked {}
+
+pub fn is_locked_error(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| cause.is::<Locked>())
+}
+
+/// Salt for the passphrase and a sealed check value. Kept next to the
+/// history; neither is secret.
+#[derive(Serialize, Deserialize)]
+struct PassphraseFile {
+    salt: String,
+    check: String,
+}
+
+fn passphrase_path() -> Result<PathBuf> {
+    Ok(crate::get_data_dir()?.join("key.json"))
+}
+
+pub fn is_sealed(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+fn seal_with(key: &[u8; KEY_LEN], plaintext: &[u8]) -> Vec<u8> {
+    let cipher = XChaCha20Poly1305::new(key.into());
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .expect("XChaCha20-Poly1305 encryption cannot fail for in-memory data");
+    [MAGIC, nonce.as_slice(), &ciphertext].concat()
+}
+
+fn unseal_with(key: &[u8; KEY_LEN], data: &[u8]) -> Result<Vec<u8>> {
+    let body = data.strip_prefix(MAGIC).context("Data is not encrypted")?;
+    if body.len() < NONCE_LEN {
+        bail!("Encrypted data is truncated");
+    }
+    let (nonce, ciphertext) = body.split_at(NONCE_LEN);
+    XChaCha20Poly1305::new(key.into())
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .ok()
+        .context("Encrypted data is corrupt or was sealed with a different key")
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key> {
+    let mut key = Zeroizing::new([0; KEY_LEN]);
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key[..])
+        .map_err(|e| anyhow::anyhow!("Failed to derive key from passphrase: {}", e))?;
+    Ok(key)
+}
+
+fn keyring_lookup() -> Option<Key> {
+    let mut cmd = Command::new("secret-tool");
+    cmd.arg("lookup").args(KEYRING_ATTRIBUTES);
+    let output = Zeroizing::new(mime::run_with_timeout(cmd)?);
+    let mut key = Zeroizing::new([0; KEY_LEN]);
+    hex::decode_to_slice(output.trim_ascii(), &mut key[..]).ok()?;
+    Some(key)
+}
+
+fn keyring_store(key: &Key) -> Result<()> {
+    let mut child = Command::new("secret-tool")
+        .args(["store", "--label=Asthetic Clipboard storage key"])
+        .args(KEYRING_ATTRIBUTES)
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("Failed to run secret-tool (is libsecret installed?)")?;
+    let mut stdin = child.stdin.take().context("secret-tool has no stdin")?;
+    stdin.write_all(Zeroizing::new(hex::encode(&key[..])).as_bytes())?;
+    drop(stdin);
+    if !child.wait()?.success() {
+        bail!("secret-tool could not store the key in the keyring");
+    }
+    Ok(())
+}
+
+/// The key, fetched from the keyring on first use if that's where it lives.
+fn key(config: &EncryptionConfig) -> Result<Key> {
+    let mut cached = KEY.lock().unwrap();
+    if let Some(key) = cached.as_ref() {
+        return Ok(key.clone());
+    }
+    if config.key_source == KeySource::Keyring
+        && let Some(key) = keyring_lookup()
+    {
+        *cached = Some(key.clone());
+        return Ok(key);
+    }
+    Err(Locked.into())
+}
+
+/// Encrypts `data` if encryption is on; returns it unchanged otherwise.
+pub fn seal(config: &EncryptionConfig, data: Vec<u8>) -> Result<Vec<u8>> {
+    if !config.enabled {
+        return Ok(data);
+    }
+    Ok(seal_with(&*key(config)?, &data))
+}
+
+/// Decrypts `data` if it is sealed. While encryption is on, plaintext is
+/// refused: a file swapped into the store must not be taken for one that
+/// was written here.
+pub fn unseal(config: &EncryptionConfig, data: Vec<u8>) -> Result<Vec<u8>> {
+    if !is_sealed(&data) {
+        if config.enabled {
+            bail!("Data is not encrypted although encryption is on");
+        }
+        return Ok(data);
+    }
+    unseal_with(&*key(config)?, &data)
+}
+
+// `unseal` for `migrate_store`, which meets both forms in a store that is
+// half way through an earlier, interrupted run
+fn unseal_migrating(config: &EncryptionConfig, data: Vec<u8>) -> Result<Vec<u8>> {
+    if !is_sealed(&data) {
+        return Ok(data);
+    }
+    unseal_with(&*key(config)?, &data)
+}
+
+/// Whether encryption is on but the key isn't available to this process.
+pub fn is_locked(config: &EncryptionConfig) -> bool {
+    config.enabled && key(config).is_err()
+}
+
+/// Makes the key available to this process: tries the keyring again, or
+/// derives the key from `passphrase` and checks it.
+pub fn unlock(config: &EncryptionConfig, passphrase: Option<&str>) -> Result<()> {
+    if config.key_source == KeySource::Keyring {
+        return key(config).map(drop).context("The key could not be read from the keyring");
+    }
+    let passphrase = passphrase.context("A passphrase is needed to unlock the history")?;
+    let content = fs::read_to_string(passphrase_path()?)
+        .context("Failed to read the passphrase salt")?;
+    let file: PassphraseFile = serde_json::from_str(&content)
+        .context("Failed to parse the passphrase salt")?;
+    let key = derive_key(passphrase, &hex::decode(&file.salt)?)?;
+    if unseal_with(&key, &hex::decode(&file.check)?).is_err() {
+        bail!("Wrong passphrase");
+    }
+    *KEY.lock().unwrap() = Some(key);
+    Ok(())
+}
+
+/// Unlocks this process and, when it is running, the daemon. This is what
+/// the front-ends' unlock prompts call.
+pub fn unlock_session(passphrase: Option<&str>) -> Result<()> {
+    let config = AppConfig::load().unwrap_or_default().encryption;
+    unlock(&config, passphrase)?;
+    if let Ok(mut client) = ipc::DaemonClient::connect() {
+        client.unlock(passphrase)?;
+    }
+    Ok(())
+}
+
+/// Sets up a fresh key for `source` and unlocks with it.
+pub fn create_key(source: KeySource, passphrase: Option<&str>) -> Result<()> {
+    let key = match source {
+        KeySource::Keyring => {
+            let mut key = Zeroizing::new([0; KEY_LEN]);
+            OsRng.fill_bytes(&mut key[..]);
+            keyring_store(&key)?;
+            key
+        }
+        KeySource::Passphrase => {
+            let passphrase = passphrase.context("A passphrase is needed")?;
+            let mut salt = [0; SALT_LEN];
+            OsRng.fill_bytes(&mut salt);
+            let key = derive_key(passphrase, &salt)?;
+            let file = PassphraseFile {
+                salt: hex::encode(salt),
+                check: hex::encode(seal_with(&key, CHECK_PLAINTEXT)),
+            };
+            fs::create_dir_all(crate::get_data_dir()?)?;
+            fs::write(passphrase_path()?, serde_json::to_string_pretty(&file)?)?;
+            key
+        }
+    };
+    *KEY.lock().unwrap() = Some(key);
+    Ok(())
+}
+
+/// Reads a file written by `write_file`, decrypting it if needed.
+pub fn read_file(config: &EncryptionConfig, path: &Path) -> Result<Vec<u8>> {
+    let data = fs::read(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    unseal(config, data)
+}
+
+/// Size of what `read_file` returns for `path`, from the file's length
+/// rather than by reading and decrypting it.
+pub fn plaintext_len(path: &Path) -> Result<u64> {
+    let mut file = fs::File::open(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let len = file.metadata()?.len();
+    let mut magic = [0; MAGIC.len()];
+    if file.read_exact(&mut magic).is_ok() && magic == MAGIC {
+        return Ok(len.saturating_sub((MAGIC.len() + NONCE_LEN + TAG_LEN) as u64));
+    }
+    Ok(len)
+}
+
+/// Atomically replaces `path` with `data`, encrypted if encryption is on.
+pub fn write_file(config: &EncryptionConfig, path: &Path, data: &[u8]) -> Result<()> {
+    let sealed = seal(config, data.to_vec())?;
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, sealed)
+        .with_context(|| format!("Failed to write {}", temp_path.display()))?;
+    fs::rename(&temp_path, path)
+        .with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+/// Encrypts or decrypts the existing history and its images in place, then
+/// records the new state in `AppConfig`. The key must already be set up
+/// (`create_key`) or unlocked. Returns how many images were rewritten.
+///
+/// Images go first and the config last, and both forms read back fine, so
+/// an interrupted run can simply be repeated. Stop the daemon first: it
+/// would write with the old setting in between.
+pub fn migrate_store(encrypt: bool) -> Result<usize> {
+    let mut config = AppConfig::load()?;
+    if config.backend() != BackendKind::Json {
+        bail!("{}", UNSUPPORTED_BACKEND);
+    }
+    let json_path = backend::json_path()?;
+    let storage = match fs::read(&json_path) {
+        Ok(data) => {
+            let content = String::from_utf8(unseal_migrating(&config.encryption, data)?)?;
+            if content.trim().is_empty() { ClipboardStorage::default() } else { ClipboardStorage::from_json(&content)? }
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => ClipboardStorage::default(),
+        Err(e) => return Err(e).context("Failed to read the history"),
+    };
+
+    config.encryption.enabled = encrypt;
+    let mut images = 0;
+    for item in storage.history.iter().filter(|item| item.kind == ClipKind::Image) {
+        let path = Path::new(&item.content);
+        // Already gone; nothing to protect
+        let Ok(data) = fs::read(path) else { continue };
+        let data = unseal_migrating(&config.encryption, data)?;
+        write_file(&config.encryption, path, &data)?;
+        images += 1;
+    }
+    // Made again on demand, in the new form
+    BlobStore::open()?.clear_thumbnails()?;
+    JsonBackend::encrypted(json_path, config.encryption.clone()).persist(&storage, &[])?;
+    config.save()?;
+    Ok(images)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_round_trip() {
+        let key = [7; KEY_LEN];
+        let sealed = seal_with(&key, b"secret clip");
+        assert!(is_sealed(&sealed));
+        assert!(!sealed.windows(11).any(|w| w == b"secret clip"));
+        assert_eq!(unseal_with(&key, &sealed).unwrap(), b"secret clip");
+
+        // Tampering and the wrong key are both caught
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(unseal_with(&key, &tampered).is_err());
+        assert!(unseal_with(&[8; KEY_LEN], &sealed).is_err());
+        assert!(unseal_with(&key, &sealed[..MAGIC.len() + 4]).is_err());
+
+        // Plaintext needs no key
+        let config = EncryptionConfig::default();
+        assert_eq!(unseal(&config, b"{}".to_vec()).unwrap(), b"{}");
+        assert_eq!(seal(&config, b"{}".to_vec()).unwrap(), b"{}");
+
+        // but once encryption is on it is refused, except while migrating
+        let enabled = EncryptionConfig { enabled: true, ..Default::default() };
+        assert!(unseal(&enabled, b"{}".to_vec()).is_err());
+        assert_eq!(unseal_migrating(&enabled, b"{}".to_vec()).unwrap(), b"{}");
+
+        // Sizes are those of the plaintext either way
+        let dir = crate::test_dir("seal");
+        fs::write(dir.join("sealed"), &sealed).unwrap();
+        fs::write(dir.join("plain"), b"secret clip").unwrap();
+        assert_eq!(plaintext_len(&dir.join("sealed")).unwrap(), 11);
+        assert_eq!(plaintext_len(&dir.join("plain")).unwrap(), 11);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_passphrase_key() {
+        let salt = [1; SALT_LEN];
+        let key = derive_key("correct horse", &salt).unwrap();
+        assert_eq!(*key, *derive_key("correct horse", &salt).unwrap());
+        let check = seal_with(&key, CHECK_PLAINTEXT);
+        let wrong = derive_key("battery staple", &salt).unwrap();
+        assert!(unseal_with(&wrong, &check).is_err());
+        assert!(is_locked_error(&anyhow::Error::from(Locked).context("Failed to load")));
+    }
+}
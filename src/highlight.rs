@@ -0,0 +1,416 @@
+use serde::{Deserialize, Serialize};
+
+/// Languages we can recognise in a text clip and highlight.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    Json,
+    Rust,
+    Python,
+    JavaScript,
+    Go,
+    /// C, C++, Java and friends
+    C,
+    Shell,
+    Sql,
+    Html,
+}
+
+impl Language {
+    pub const ALL: [Language; 9] = [
+        Language::Json, Language::Rust, Language::Python, Language::JavaScript, Language::Go,
+        Language::C, Language::Shell, Language::Sql, Language::Html,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Language::Json => "json",
+            Language::Rust => "rust",
+            Language::Python => "python",
+            Language::JavaScript => "javascript",
+            Language::Go => "go",
+            Language::C => "c",
+            Language::Shell => "shell",
+            Language::Sql => "sql",
+            Language::Html => "html",
+        }
+    }
+
+    /// Parses a name typed by the user, accepting common aliases.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        let language = match name.as_str() {
+            "json" => Language::Json,
+            "rust" | "rs" => Language::Rust,
+            "python" | "py" => Language::Python,
+            "javascript" | "js" | "typescript" | "ts" => Language::JavaScript,
+            "go" | "golang" => Language::Go,
+            "c" | "cpp" | "c++" | "java" | "cs" => Language::C,
+            "shell" | "sh" | "bash" | "zsh" => Language::Shell,
+            "sql" => Language::Sql,
+            "html" | "xml" => Language::Html,
+            _ => return None,
+        };
+        Some(language)
+    }
+
+    fn syntax(&self) -> Syntax {
+        match self {
+            Language::Json => Syntax {
+                keywords: &["true", "false", "null"],
+                line_comments: &[],
+                block_comment: None,
+                quotes: &['"'],
+            },
+            Language::Rust => Syntax {
+                keywords: &[
+                    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+                    "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod",
+                    "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super",
+                    "trait", "true", "type", "unsafe", "use", "where", "while", "Some", "None", "Ok", "Err",
+                ],
+                line_comments: &["//"],
+                block_comment: Some(("/*", "*/")),
+                quotes: &['"'],
+            },
+            Language::Python => Syntax {
+                keywords: &[
+                    "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+                    "elif", "else", "except", "False", "finally", "for", "from", "global", "if", "import",
+                    "in", "is", "lambda", "None", "nonlocal", "not", "or", "pass", "raise", "return",
+                    "self", "True", "try", "while", "with", "yield",
+                ],
+                line_comments: &["#"],
+                block_comment: None,
+                quotes: &['"', '\''],
+            },
+            Language::JavaScript => Syntax {
+                keywords: &[
+                    "async", "await", "break", "case", "catch", "class", "const", "continue", "default",
+                    "delete", "else", "export", "extends", "false", "finally", "for", "from", "function",
+                    "if", "import", "in", "instanceof", "interface", "let", "new", "null", "of", "return",
+                    "switch", "this", "throw", "true", "try", "type", "typeof", "undefined", "var",
+                    "while", "yield",
+                ],
+                line_comments: &["//"],
+                block_comment: Some(("/*", "*/")),
+                quotes: &['"', '\'', '`'],
+            },
+            Language::Go => Syntax {
+                keywords: &[
+                    "break", "case", "chan", "const", "continue", "default", "defer", "else", "false",
+                    "for", "func", "go", "if", "import", "interface", "map", "nil", "package", "range",
+                    "return", "select", "struct", "switch", "true", "type", "var",
+                ],
+                line_comments: &["//"],
+                block_comment: Some(("/*", "*/")),
+                quotes: &['"', '`'],
+            },
+            Language::C => Syntax {
+                keywords: &[
+                    "auto", "bool", "break", "case", "catch", "char", "class", "const", "continue",
+                    "default", "do", "double", "else", "enum", "extends", "false", "final", "float",
+                    "for", "if", "import", "int", "long", "namespace", "new", "null", "NULL", "nullptr",
+                    "private", "protected", "public", "return", "short", "static", "struct", "switch",
+                    "template", "this", "throw", "true", "try", "typedef", "unsigned", "using", "void",
+                    "while", "#include", "#define",
+                ],
+                line_comments: &["//"],
+                block_comment: Some(("/*", "*/")),
+                quotes: &['"', '\''],
+            },
+            Language::Shell => Syntax {
+                keywords: &[
+                    "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if",
+                    "in", "local", "return", "then", "until", "while", "sudo",
+                ],
+                line_comments: &["#"],
+                block_comment: None,
+                quotes: &['"', '\''],
+            },
+            Language::Sql => Syntax {
+                keywords: &[
+                    "add", "alter", "and", "as", "asc", "by", "create", "delete", "desc", "distinct",
+                    "drop", "from", "group", "having", "in", "index", "inner", "insert", "into", "is",
+                    "join", "key", "left", "like", "limit", "not", "null", "on", "or", "order", "outer",
+                    "primary", "right", "select", "set", "table", "union", "update", "values", "where",
+                ],
+                line_comments: &["--"],
+                block_comment: Some(("/*", "*/")),
+                quotes: &['\'', '"'],
+            },
+            Language::Html => Syntax {
+                keywords: &[],
+                line_comments: &[],
+                block_comment: Some(("<!--", "-->")),
+                quotes: &['"', '\''],
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Language {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What a highlighted piece of text is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    Plain,
+    Keyword,
+    String,
+    Number,
+    Comment,
+    /// Object keys in JSON
+    Key,
+    /// Markup tags in HTML
+    Tag,
+}
+
+struct Syntax {
+    keywords: &'static [&'static str],
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+}
+
+// Commands that make a one-line clip a shell command
+const SHELL_COMMANDS: &[&str] = &[
+    "apt", "brew", "cargo", "cat", "cd", "chmod", "chown", "cp", "curl", "dnf", "docker", "echo",
+    "export", "find", "git", "grep", "kubectl", "ls", "make", "mkdir", "mv", "npm", "npx", "pacman",
+    "pip", "rm", "rsync", "scp", "sed", "ssh", "sudo", "systemctl", "tar", "wget", "yarn",
+];
+
+/// Guesses the language of a clip. `None` means it looks like prose or
+/// anything else we don't highlight.
+pub fn detect(text: &str) -> Option<Language> {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return None;
+    }
+
+    if (trimmed.starts_with('{') || trimmed.starts_with('['))
+        && serde_json::from_str::<serde_json::Value>(trimmed).is_ok()
+    {
+        return Some(Language::Json);
+    }
+    if trimmed.starts_with('<') && trimmed.ends_with('>') && (trimmed.contains("</") || trimmed.contains("/>")) {
+        return Some(Language::Html);
+    }
+    if trimmed.starts_with("#!") {
+        let shebang = trimmed.lines().next().unwrap_or_default();
+        if shebang.contains("python") {
+            return Some(Language::Python);
+        }
+        if shebang.contains("node") {
+            return Some(Language::JavaScript);
+        }
+        return Some(Language::Shell);
+    }
+
+    let lower = trimmed.to_lowercase();
+    // Each pattern is one hint; the language with the most wins
+    let hints: [(Language, &[&str]); 7] = [
+        (Language::Rust, &["fn ", "let mut ", "impl ", "pub fn", "use std", "::", "-> ", "#[derive", "&self", "&mut ", "println!", "match ", "unwrap()"]),
+        (Language::Python, &["def ", "self.", "elif ", "print(", "__init__", "None", "lambda ", "):\n"]),
+        (Language::JavaScript, &["function ", "const ", "=> ", "console.log", "require(", "export ", "===", "document.", "async ", "let "]),
+        (Language::Go, &["func ", "package ", ":= ", "fmt.", "err != nil", "go func", "defer "]),
+        (Language::C, &["#include", "int main", "printf(", "std::", "void ", "public class", "system.out", "nullptr", "->"]),
+        (Language::Sql, &["select ", "insert into", "update ", "delete from", "create table", " where ", " join ", "group by", "order by"]),
+        (Language::Shell, &["| grep", "&&", "$(", "${", " --", "sudo ", "export ", "echo "]),
+    ];
+    let mut best: Option<(Language, usize)> = None;
+    for (language, patterns) in hints {
+        // SQL and shell are matched case-insensitively
+        let haystack = if matches!(language, Language::Sql | Language::Shell) { &lower } else { trimmed };
+        let mut score = patterns.iter().filter(|p| haystack.contains(*p)).count();
+        let python_import = |line: &str| {
+            line.starts_with("import ") || (line.starts_with("from ") && line.contains(" import "))
+        };
+        if language == Language::Python && trimmed.lines().any(python_import) {
+            score += 1;
+        }
+        if language == Language::Shell && is_shell_command(trimmed) {
+            score += 2;
+        }
+        if language == Language::Sql && lower.starts_with("select ") && lower.contains(" from ") {
+            score += 2;
+        }
+        if score > best.map_or(0, |(_, s)| s) {
+            best = Some((language, score));
+        }
+    }
+    // A single hint is too easy to hit in ordinary prose
+    best.filter(|&(_, score)| score >= 2).map(|(language, _)| language)
+}
+
+/// One or two lines starting with a well-known command.
+fn is_shell_command(text: &str) -> bool {
+    if text.lines().count() > 2 {
+        return false;
+    }
+    let first = text.trim_start_matches("$ ").split_whitespace().next().unwrap_or_default();
+    SHELL_COMMANDS.contains(&first)
+}
+
+/// Splits `text` into highlighted pieces that together cover all of it.
+pub fn highlight(text: &str, language: Language) -> Vec<(&str, Token)> {
+    let syntax = language.syntax();
+    let mut tokens: Vec<(&str, Token)> = Vec::new();
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    let mut plain_start = 0;
+    while i < text.len() {
+        let rest = &text[i..];
+        let prev = text[..i].chars().next_back();
+        let (len, token) = if let Some(len) = comment_len(rest, prev, &syntax) {
+            (len, Token::Comment)
+        } else if language == Language::Html && rest.starts_with('<') {
+            (rest.find('>').map_or(rest.len(), |end| end + 1), Token::Tag)
+        } else if let Some(quote) = rest.chars().next().filter(|c| syntax.quotes.contains(c)) {
+            let len = string_len(rest, quote);
+            let token = if language == Language::Json && text[i + len..].trim_start().starts_with(':') {
+                Token::Key
+            } else {
+                Token::String
+            };
+            (len, token)
+        } else if bytes[i].is_ascii_digit() && !prev.is_some_and(is_ident_char) {
+            (rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '_')).unwrap_or(rest.len()), Token::Number)
+        } else if rest.starts_with(|c: char| c.is_alphabetic() || c == '_' || c == '#') && !prev.is_some_and(is_ident_char) {
+            let first = rest.chars().next().map_or(1, char::len_utf8);
+            let len = rest[first..].find(|c: char| !is_ident_char(c)).map_or(rest.len(), |n| n + first);
+            let word = &rest[..len];
+            let keyword = if language == Language::Sql {
+                syntax.keywords.contains(&word.to_lowercase().as_str())
+            } else {
+                syntax.keywords.contains(&word)
+            };
+            (len, if keyword { Token::Keyword } else { Token::Plain })
+        } else {
+            (rest.chars().next().map_or(1, char::len_utf8), Token::Plain)
+        };
+
+        if token != Token::Plain {
+            if plain_start < i {
+                tokens.push((&text[plain_start..i], Token::Plain));
+            }
+            tokens.push((&text[i..i + len], token));
+            plain_start = i + len;
+        }
+        i += len;
+    }
+    if plain_start < text.len() {
+        tokens.push((&text[plain_start..], Token::Plain));
+    }
+    tokens
+}
+
+/// `highlight`, split into lines the way `str::lines` splits them.
+pub fn highlight_lines(text: &str, language: Language) -> Vec<Vec<(&str, Token)>> {
+    let mut lines = vec![Vec::new()];
+    for (piece, token) in highlight(text, language) {
+        let mut parts = piece.split('\n').peekable();
+        while let Some(part) = parts.next() {
+            let ends_line = parts.peek().is_some();
+            let part = if ends_line { part.strip_suffix('\r').unwrap_or(part) } else { part };
+            if !part.is_empty() {
+                lines.last_mut().unwrap().push((part, token));
+            }
+            if ends_line {
+                lines.push(Vec::new());
+            }
+        }
+    }
+    // `str::lines` drops the empty line after a final newline
+    if text.ends_with('\n') {
+        lines.pop();
+    }
+    lines
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn comment_len(rest: &str, prev: Option<char>, syntax: &Syntax) -> Option<usize> {
+    for marker in syntax.line_comments {
+        // `#` only starts a comment at a word boundary (not in `$#` or `a#b`)
+        if rest.starts_with(marker) && (*marker != "#" || prev.is_none_or(char::is_whitespace)) {
+            return Some(rest.find('\n').unwrap_or(rest.len()));
+        }
+    }
+    if let Some((open, close)) = syntax.block_comment
+        && rest.starts_with(open)
+    {
+        return Some(rest[open.len()..].find(close).map_or(rest.len(), |end| open.len() + end + close.len()));
+    }
+    None
+}
+
+/// Length of the string literal at the start of `rest`, including quotes.
+/// Only backticks span lines; an unterminated string ends at the newline.
+fn string_len(rest: &str, quote: char) -> usize {
+    let mut escaped = false;
+    for (i, c) in rest.char_indices().skip(1) {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == quote {
+            return i + c.len_utf8();
+        } else if c == '\n' && quote != '`' {
+            return i;
+        }
+    }
+    rest.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect(r#"{"a": [1, 2]}"#), Some(Language::Json));
+        assert_eq!(detect("fn main() {\n    println!(\"hi\");\n}"), Some(Language::Rust));
+        assert_eq!(detect("def f(self):\n    return None"), Some(Language::Python));
+        assert_eq!(detect("git commit -m 'wip'"), Some(Language::Shell));
+        assert_eq!(detect("SELECT id FROM users WHERE name = 'x'"), Some(Language::Sql));
+        assert_eq!(detect("<div class=\"a\">hi</div>"), Some(Language::Html));
+        assert_eq!(detect("Meet me at the station at five."), None);
+        assert_eq!(detect("I'll import none of the data from the file"), None);
+    }
+
+    #[test]
+    fn test_highlight() {
+        let tokens = highlight(r#"{"name": "x", "n": 10, "ok": true}"#, Language::Json);
+        assert!(tokens.contains(&(r#""name""#, Token::Key)));
+        assert!(tokens.contains(&(r#""x""#, Token::String)));
+        assert!(tokens.contains(&("10", Token::Number)));
+        assert!(tokens.contains(&("true", Token::Keyword)));
+
+        let text = "let x = 1; // one\nlet s = \"a\\\"b\";\n";
+        let pieces = highlight(text, Language::Rust);
+        assert_eq!(pieces.iter().map(|(p, _)| *p).collect::<String>(), text);
+        assert!(pieces.contains(&("// one", Token::Comment)));
+        assert!(pieces.contains(&("\"a\\\"b\"", Token::String)));
+        assert_eq!(highlight_lines(text, Language::Rust).len(), 2);
+    }
+
+    #[test]
+    fn test_highlight_non_ascii_words() {
+        let text = "echo élan && ls";
+        let pieces = highlight(text, Language::Shell);
+        assert_eq!(pieces.iter().map(|(p, _)| *p).collect::<String>(), text);
+
+        // Words in keyword position that start with a non-ASCII letter
+        let text = "SELECT été FROM ñandú";
+        let pieces = highlight(text, Language::Sql);
+        assert_eq!(pieces.iter().map(|(p, _)| *p).collect::<String>(), text);
+        let keywords: Vec<&str> = pieces.iter().filter(|(_, t)| *t == Token::Keyword).map(|(p, _)| *p).collect();
+        assert_eq!(keywords, vec!["SELECT", "FROM"]);
+    }
+}
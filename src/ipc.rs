@@ -0,0 +1,303 @@
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+use anyhow::{Result, Context, bail};
+use directories::ProjectDirs;
+
+use crate::crypto::Locked;
+use crate::{HistoryItem, StorageEvent};
+
+// Front-ends block on replies, so don't let a wedged daemon hang them
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// A request to the daemon. Sent as one JSON object per line.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Request {
+    List,
+    Get { hash: String },
+    Pin { hash: String, pinned: bool },
+    Delete { hash: String },
+    Clear { keep_pinned: bool },
+    /// Put the item back on the system clipboard
+    Copy { hash: String },
+    /// Keep the connection open and stream `Response::Event`s
+    Subscribe,
+    /// Make the encryption key available to the daemon
+    Unlock { passphrase: Option<String> },
+    /// The decrypted PNG of an image item, for front-ends without the key
+    Image { hash: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Response {
+    Ok,
+    Items(Vec<HistoryItem>),
+    Item(Option<HistoryItem>),
+    Event(StorageEvent),
+    /// Raw bytes. Only the length goes in the JSON line; the bytes follow
+    /// it as they are (see `write_response`).
+    Data {
+        len: usize,
+        #[serde(skip)]
+        bytes: Vec<u8>,
+    },
+    /// The history is encrypted and the daemon doesn't have the key yet
+    Locked,
+    Error(String),
+}
+
+impl Response {
+    pub fn data(bytes: Vec<u8>) -> Self {
+        Response::Data { len: bytes.len(), bytes }
+    }
+}
+
+/// Where the daemon listens: the user's runtime dir when there is one,
+/// the data dir otherwise.
+pub fn socket_path() -> Result<PathBuf> {
+    let proj_dirs = ProjectDirs::from("com", "asthetic", "clipboard")
+        .context("Could not determine project directories")?;
+    let dir = proj_dirs.runtime_dir().unwrap_or(proj_dirs.data_dir());
+    std::fs::create_dir_all(dir)?;
+    Ok(dir.join("daemon.sock"))
+}
+
+pub fn write_message<T: Serialize>(stream: &mut impl Write, message: &T) -> Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    stream.write_all(&line)?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Writes a reply to a request, followed by the bytes of `Response::Data`.
+pub fn write_response(stream: &mut impl Write, response: &Response) -> Result<()> {
+    write_message(stream, response)?;
+    if let Response::Data { bytes, .. } = response {
+        stream.write_all(bytes)?;
+        stream.flush()?;
+    }
+    Ok(())
+}
+
+/// Reads a reply written by `write_response`.
+pub fn read_response(reader: &mut impl BufRead) -> Result<Option<Response>> {
+    let mut response = read_message(reader)?;
+    if let Some(Response::Data { len, bytes }) = &mut response {
+        bytes.resize(*len, 0);
+        reader.read_exact(bytes).context("Truncated IPC data")?;
+    }
+    Ok(response)
+}
+
+/// Reads one message, or `None` when the peer closed the connection.
+pub fn read_message<T: for<'de> Deserialize<'de>>(reader: &mut impl BufRead) -> Result<Option<T>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&line).context("Malformed IPC message")?))
+}
+
+/// Connection to the running daemon.
+pub struct DaemonClient {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl DaemonClient {
+    pub fn connect() -> Result<Self> {
+        let stream = UnixStream::connect(socket_path()?)
+            .context("Clipboard daemon is not running")?;
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+        Self::from_stream(stream)
+    }
+
+    pub fn from_stream(stream: UnixStream) -> Result<Self> {
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        })
+    }
+
+    fn call(&mut self, request: &Request) -> Result<Response> {
+        write_message(&mut self.writer, request)?;
+        match read_response(&mut self.reader)? {
+            Some(Response::Error(e)) => bail!("Daemon error: {}", e),
+            Some(Response::Locked) => Err(Locked.into()),
+            Some(response) => Ok(response),
+            None => bail!("Daemon closed the connection"),
+        }
+    }
+
+    fn call_ok(&mut self, request: &Request) -> Result<()> {
+        match self.call(request)? {
+            Response::Ok => Ok(()),
+            other => bail!("Unexpected daemon response: {:?}", other),
+        }
+    }
+
+    pub fn list(&mut self) -> Result<Vec<HistoryItem>> {
+        match self.call(&Request::List)? {
+            Response::Items(items) => Ok(items),
+            other => bail!("Unexpected daemon response: {:?}", other),
+        }
+    }
+
+    pub fn get(&mut self, hash: &str) -> Result<Option<HistoryItem>> {
+        match self.call(&Request::Get { hash: hash.to_string() })? {
+            Response::Item(item) => Ok(item),
+            other => bail!("Unexpected daemon response: {:?}", other),
+        }
+    }
+
+    pub fn set_pinned(&mut self, hash: &str, pinned: bool) -> Result<()> {
+        self.call_ok(&Request::Pin { hash: hash.to_string(), pinned })
+    }
+
+    pub fn delete(&mut self, hash: &str) -> Result<()> {
+        self.call_ok(&Request::Delete { hash: hash.to_string() })
+    }
+
+    pub fn clear(&mut self, keep_pinned: bool) -> Result<()> {
+        self.call_ok(&Request::Clear { keep_pinned })
+    }
+
+    pub fn copy(&mut self, hash: &str) -> Result<()> {
+        self.call_ok(&Request::Copy { hash: hash.to_string() })
+    }
+
+    pub fn unlock(&mut self, passphrase: Option<&str>) -> Result<()> {
+        self.call_ok(&Request::Unlock { passphrase: passphrase.map(str::to_string) })
+    }
+
+    pub fn image(&mut self, hash: &str) -> Result<Vec<u8>> {
+        match self.call(&Request::Image { hash: hash.to_string() })? {
+            Response::Data { bytes, .. } => Ok(bytes),
+            other => bail!("Unexpected daemon response: {:?}", other),
+        }
+    }
+
+    /// Forwards changes made to a `ClipboardStorage` loaded from the daemon.
+    pub fn apply(&mut self, events: &[StorageEvent]) -> Result<()> {
+        for event in events {
+            match event {
+                StorageEvent::Pinned(hash) => self.set_pinned(hash, true)?,
+                StorageEvent::Unpinned(hash) => self.set_pinned(hash, false)?,
+                StorageEvent::Deleted(hash) => self.delete(hash)?,
+                StorageEvent::Cleared { keep_pinned } => self.clear(*keep_pinned)?,
+                // Only the daemon records new clips
+                StorageEvent::Added(_) => bail!("Clients cannot add history items"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Turns this connection into a stream of change events.
+    pub fn subscribe(mut self) -> Result<Subscription> {
+        self.call_ok(&Request::Subscribe)?;
+        // Events arrive whenever something changes, possibly never
+        self.reader.get_ref().set_read_timeout(None)?;
+        Ok(Subscription { reader: self.reader })
+    }
+}
+
+/// Change events pushed by the daemon. Iteration ends when it goes away.
+pub struct Subscription {
+    reader: BufReader<UnixStream>,
+}
+
+impl Iterator for Subscription {
+    type Item = StorageEvent;
+
+    fn next(&mut self) -> Option<StorageEvent> {
+        loop {
+            match read_message(&mut self.reader) {
+                Ok(Some(Response::Event(event))) => return Some(event),
+                Ok(Some(_)) => continue,
+                Ok(None) | Err(_) => return None,
+            }
+        }
+    }
+}
+
+/// A change made to the history by another process.
+#[derive(Debug, Clone)]
+pub enum Change {
+    Event(Box<StorageEvent>),
+    /// Sent on every (re)subscription, as events may have been missed
+    /// before it; reload
+    Resync,
+}
+
+/// Follows the daemon's change stream on a background thread, reconnecting
+/// whenever it goes away. Front-ends drain the receiver from their UI loop.
+pub fn watch() -> Receiver<Change> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        loop {
+            if let Ok(subscription) = DaemonClient::connect().and_then(DaemonClient::subscribe) {
+                // Whatever changed before the subscription, even just since
+                // the caller loaded the history, only shows up on a reload
+                if tx.send(Change::Resync).is_err() {
+                    return;
+                }
+                for event in subscription {
+                    if tx.send(Change::Event(Box::new(event))).is_err() {
+                        return;
+                    }
+                }
+            }
+            thread::sleep(RECONNECT_DELAY);
+        }
+    });
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ClipboardStorage, ClipKind};
+
+    #[test]
+    fn test_client_round_trip() {
+        let (client_end, server_end) = UnixStream::pair().unwrap();
+
+        let server = std::thread::spawn(move || {
+            let mut storage = ClipboardStorage::default();
+            storage.add("hello".to_string(), ClipKind::Text, None, 50);
+
+            let mut reader = BufReader::new(server_end.try_clone().unwrap());
+            let mut writer = server_end;
+            while let Some(request) = read_message::<Request>(&mut reader).unwrap() {
+                let response = match request {
+                    Request::List => Response::Items(storage.history.clone()),
+                    Request::Delete { .. } => Response::Error("no such item".to_string()),
+                    Request::Image { .. } => Response::data(vec![0, b'\n', 255]),
+                    _ => Response::Ok,
+                };
+                write_response(&mut writer, &response).unwrap();
+            }
+        });
+
+        let mut client = DaemonClient::from_stream(client_end).unwrap();
+        let items = client.list().unwrap();
+        assert_eq!(items[0].content, "hello");
+        assert!(client.set_pinned(items[0].id(), true).is_ok());
+        assert!(client.delete("missing").is_err());
+        // Raw bytes, newlines included, and the stream stays in step
+        assert_eq!(client.image(items[0].id()).unwrap(), vec![0, b'\n', 255]);
+        assert_eq!(client.list().unwrap().len(), 1);
+
+        drop(client);
+        server.join().unwrap();
+    }
+}
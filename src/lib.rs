@@ -1,11 +1,36 @@
 use serde::{Deserialize, Serialize};
-use std::fs::{self, OpenOptions};
-use std::path::PathBuf;
-use std::io::{Read, Write};
-use chrono::{DateTime, Local};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use chrono::{DateTime, Local, TimeDelta};
 use anyhow::{Result, Context};
 use directories::ProjectDirs;
-use fs2::FileExt;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+
+use blob::{BlobStore, ImageMeta};
+use category::Category;
+use crypto::EncryptionConfig;
+use highlight::Language;
+use mime::Representations;
+use secrets::SecretsConfig;
+use source::SourceApp;
+
+pub mod backend;
+pub mod blob;
+pub mod category;
+pub mod clipboard;
+pub mod crypto;
+pub mod highlight;
+pub mod ipc;
+pub mod mime;
+pub mod search;
+pub mod secrets;
+pub mod source;
+pub mod watcher;
+
+pub use backend::{BackendKind, HistoryBackend};
+pub use search::{SearchHit, SearchMode, SearchQuery};
 
 // Defaults
 const DEFAULT_MAX_HISTORY: usize = 50;
@@ -16,6 +41,31 @@ pub struct AppConfig {
     pub max_history: usize,
     pub theme: String, // "dark" or "light"
     pub start_login: bool,
+    /// Where the history is kept. Ignored while encryption is on, which
+    /// only the json backend supports; see `backend`.
+    #[serde(default)]
+    pub storage_backend: BackendKind,
+    /// How clips containing passwords, keys and the like are recorded
+    #[serde(default)]
+    pub secrets: SecretsConfig,
+    /// Applications whose copies are never recorded, by window class or
+    /// instance name (e.g. "keepassxc", "org.gnome.Terminal"). Works on
+    /// X11 and Hyprland only: other Wayland compositors don't say which
+    /// app copied a clip, so nothing is ignored there.
+    #[serde(default)]
+    pub ignored_apps: Vec<String>,
+    #[serde(default)]
+    pub encryption: EncryptionConfig,
+    /// Age and size limits on top of `max_history`
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    /// Private mode: nothing is recorded while it is on
+    #[serde(default)]
+    pub private_mode: bool,
+    /// When private mode turns itself off; `None` keeps it on until it is
+    /// switched off
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub private_until: Option<DateTime<Local>>,
 }
 
 impl Default for AppConfig {
@@ -24,6 +74,13 @@ impl Default for AppConfig {
             max_history: DEFAULT_MAX_HISTORY,
             theme: DEFAULT_THEME.to_string(),
             start_login: false,
+            storage_backend: BackendKind::default(),
+            secrets: SecretsConfig::default(),
+            ignored_apps: Vec::new(),
+            encryption: EncryptionConfig::default(),
+            retention: RetentionConfig::default(),
+            private_mode: false,
+            private_until: None,
         }
     }
 }
@@ -39,6 +96,44 @@ impl AppConfig {
         Ok(config)
     }
 
+    /// When the config file was last written, to tell whether a loaded
+    /// config is still current. `None` if there is no file yet.
+    pub fn modified() -> Option<SystemTime> {
+        fs::metadata(get_config_path().ok()?).ok()?.modified().ok()
+    }
+
+    /// The backend the history is actually kept in. An encrypted history
+    /// is always `history.json`: `daemon encrypt` refuses other backends,
+    /// so switching `storage_backend` afterwards must not lose track of it.
+    pub fn backend(&self) -> BackendKind {
+        if self.encryption.enabled {
+            BackendKind::Json
+        } else {
+            self.storage_backend
+        }
+    }
+
+    /// Whether clips copied from `source` should not be recorded.
+    pub fn is_ignored(&self, source: &SourceApp) -> bool {
+        self.ignored_apps.iter().any(|name| source.matches(name))
+    }
+
+    /// Whether private mode is on at `now`.
+    pub fn is_private(&self, now: DateTime<Local>) -> bool {
+        self.private_mode && self.private_until.is_none_or(|until| now < until)
+    }
+
+    /// Turns private mode on, for `duration` or until switched off, or off.
+    /// Works on a fresh copy of the config, since the daemon and every
+    /// front-end share it, and returns what was saved.
+    pub fn set_private(on: bool, duration: Option<chrono::Duration>) -> Result<Self> {
+        let mut config = Self::load()?;
+        config.private_mode = on;
+        config.private_until = duration.filter(|_| on).map(|d| Local::now() + d);
+        config.save()?;
+        Ok(config)
+    }
+
     pub fn save(&self) -> Result<()> {
         let path = get_config_path()?;
         if let Some(parent) = path.parent() {
@@ -50,105 +145,379 @@ impl AppConfig {
     }
 }
 
+/// How long items are kept and how much space images may take, on top
+/// of `max_history`. The daemon enforces these at startup and then every
+/// few seconds. Pinned items are never removed.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// Delete items first copied more than this many days ago
+    pub max_age_days: Option<u32>,
+    /// Delete items with secrets in them (see `HistoryItem::sensitive`)
+    /// this many minutes after they were copied
+    pub sensitive_max_age_mins: Option<u32>,
+    /// Delete the oldest images once all of them together take up more
+    /// than this many MB
+    pub max_images_mb: Option<u64>,
+}
+
+/// What a history entry holds. Serialized in snake_case so the legacy
+/// "text"/"image" strings in `history.json` map onto it unchanged.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ClipKind {
+    #[default]
+    Text,
+    Image,
+    Html,
+    Files,
+    RichText,
+}
+
+impl ClipKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ClipKind::Text => "text",
+            ClipKind::Image => "image",
+            ClipKind::Html => "html",
+            ClipKind::Files => "files",
+            ClipKind::RichText => "rich_text",
+        }
+    }
+}
+
+impl std::fmt::Display for ClipKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct HistoryItem {
     pub content: String, // Text content OR Path to image file
     pub timestamp: DateTime<Local>,
     #[serde(default)]
     pub pinned: bool,
-    #[serde(default = "default_kind")]
-    pub kind: String, // "text" or "image"
+    #[serde(default)]
+    pub kind: ClipKind,
     #[serde(default)]
     pub hash: Option<String>, // For image deduplication
+    /// Other formats the clip was offered in (HTML, RTF, URI lists),
+    /// keyed by MIME type. `content` stays the plain-text version.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub representations: Representations,
+    /// Programming language of a code snippet, detected when copied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<Language>,
+    /// What kind of text this is (URL, colour, ...), if recognisable
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<Category>,
+    /// When the item is deleted on its own (set for secrets, see
+    /// `secrets::SecretPolicy::Expire`). Pinning it keeps it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Local>>,
+    /// Application and window the clip was copied from, where known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<SourceApp>,
+    /// Whether secrets were found in the clip, so it was masked or set to
+    /// expire. `RetentionConfig::sensitive_max_age_mins` applies to these.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sensitive: bool,
+    /// Dimensions and size of an `Image` item, recorded since the blob
+    /// store. Use `ImageMeta::of` to cover older items too.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<ImageMeta>,
+}
+
+/// Everything recorded about a new clip besides its content and kind.
+#[derive(Debug, Clone, Default)]
+pub struct ClipMeta {
+    /// Other formats the clip was offered in, see `HistoryItem::representations`
+    pub representations: Representations,
+    /// When `remove_expired` deletes the item
+    pub expires_at: Option<DateTime<Local>>,
+    pub source: Option<SourceApp>,
+    /// See `HistoryItem::sensitive`
+    pub sensitive: bool,
+    pub image: Option<ImageMeta>,
+}
+
+impl HistoryItem {
+    /// A plain-text item copied now, with nothing else recorded about it.
+    pub fn text(content: &str) -> Self {
+        Self {
+            content: content.to_string(),
+            timestamp: Local::now(),
+            pinned: false,
+            kind: ClipKind::Text,
+            hash: None,
+            representations: Representations::new(),
+            language: None,
+            category: None,
+            expires_at: None,
+            source: None,
+            sensitive: false,
+            image: None,
+        }
+    }
+
+    /// Stable identifier used by storage backends. Every item carries a
+    /// hash since schema v2: the pixel hash for images, the content hash
+    /// for everything else.
+    pub fn id(&self) -> &str {
+        self.hash.as_deref().unwrap_or_default()
+    }
+
+    /// The files a `Files` item refers to, taken from its URI list.
+    pub fn file_paths(&self) -> Vec<PathBuf> {
+        match self.representations.get(mime::URI_LIST) {
+            Some(uri_list) => mime::uri_list_paths(uri_list),
+            None => self.content.lines().map(PathBuf::from).collect(),
+        }
+    }
+
+    /// The file an `Image` item's picture is stored in.
+    pub fn image_path(&self) -> Option<&Path> {
+        (self.kind == ClipKind::Image).then(|| Path::new(&self.content))
+    }
+
+    /// The PNG bytes of an `Image` item, decrypted if need be. Front-ends
+    /// that haven't unlocked themselves get them from the daemon.
+    pub fn image_data(&self, encryption: &EncryptionConfig) -> Result<Vec<u8>> {
+        match crypto::read_file(encryption, Path::new(&self.content)) {
+            Err(e) if crypto::is_locked_error(&e) => match ipc::DaemonClient::connect() {
+                Ok(mut client) => client.image(self.id()),
+                Err(_) => Err(e),
+            },
+            result => result,
+        }
+    }
 }
 
-fn default_kind() -> String {
-    "text".to_string()
+/// SHA-256 of a text payload, hex encoded. Used as the identity of
+/// non-image items.
+pub fn content_hash(content: &str) -> String {
+    hex::encode(Sha256::digest(content.as_bytes()))
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+/// Identity of a text payload together with its other formats. Equal to
+/// `content_hash` when there are none, so plain text keeps its id.
+pub fn clip_hash(content: &str, representations: &Representations) -> String {
+    if representations.is_empty() {
+        return content_hash(content);
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(content.as_bytes());
+    for (mime, data) in representations {
+        hasher.update([0]);
+        hasher.update(mime.as_bytes());
+        hasher.update([0]);
+        hasher.update(data.as_bytes());
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Version of the on-disk history format. Bump it and add a step to
+/// `ClipboardStorage::migrate` whenever the layout changes.
+pub const SCHEMA_VERSION: u32 = 4;
+
+/// A single change to the history. Mutating methods on `ClipboardStorage`
+/// queue these so incremental backends can persist just the delta.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageEvent {
+    /// Item inserted at the front, replacing any entry with the same id
+    Added(HistoryItem),
+    Pinned(String),
+    Unpinned(String),
+    Deleted(String),
+    Cleared { keep_pinned: bool },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct ClipboardStorage {
+    // Files written before versioning have no field, which reads as 0
+    #[serde(default)]
+    pub schema_version: u32,
     pub history: Vec<HistoryItem>,
+    #[serde(skip)]
+    pending: Vec<StorageEvent>,
+    // Read position for backends that can replay changes incrementally
+    #[serde(skip)]
+    pub(crate) journal_cursor: Option<backend::journal::Cursor>,
+    // Loaded from the daemon; changes are sent back to it on save
+    #[serde(skip)]
+    remote: bool,
+    // Image files of removed items, deleted once the removal is saved
+    #[serde(skip)]
+    released: Vec<PathBuf>,
+    // Settings the history was loaded with, so saves don't reread them
+    #[serde(skip)]
+    config: Option<AppConfig>,
+}
+
+impl Default for ClipboardStorage {
+    fn default() -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            history: Vec::new(),
+            pending: Vec::new(),
+            journal_cursor: None,
+            remote: false,
+            released: Vec::new(),
+            config: None,
+        }
+    }
 }
 
 impl ClipboardStorage {
+    /// Loads the history from the backend selected in `AppConfig`.
     pub fn load() -> Result<Self> {
-        let path = get_data_path()?;
-        if !path.exists() {
-            return Ok(Self::default());
-        }
-        
-        // Open file with shared lock for reading
-        let file = OpenOptions::new()
-            .read(true)
-            .open(&path)
-            .context("Failed to open storage file")?;
-        
-        // Acquire shared lock (allows multiple readers)
-        file.lock_shared()
-            .context("Failed to acquire shared lock on storage file")?;
-        
-        let mut content = String::new();
-        let mut file_reader = file;
-        file_reader.read_to_string(&mut content)
-            .context("Failed to read storage file")?;
-        
-        // Lock is automatically released when file goes out of scope
-        
-        // Handle empty file gracefully
-        if content.trim().is_empty() {
-            return Ok(Self::default());
+        let config = AppConfig::load().unwrap_or_default();
+        let mut storage = backend::open(&config)?.load()?;
+        storage.config = Some(config);
+        Ok(storage)
+    }
+
+    // Which backend to use and how it is encrypted. Fixed for the life of
+    // the storage; one made without `load` reads the config once.
+    fn config(&mut self) -> &AppConfig {
+        self.config.get_or_insert_with(|| AppConfig::load().unwrap_or_default())
+    }
+
+    /// Loads the history through the daemon when it is running, so that
+    /// saves go through it too, and straight from disk otherwise. This is
+    /// what the front-ends use; the daemon itself uses `load`.
+    pub fn load_shared() -> Result<Self> {
+        match ipc::DaemonClient::connect() {
+            Ok(mut client) => Ok(Self {
+                history: client.list()?,
+                remote: true,
+                ..Default::default()
+            }),
+            Err(_) => Self::load(),
         }
-        
+    }
+
+    /// Whether this storage is backed by the daemon rather than disk.
+    pub fn is_remote(&self) -> bool {
+        self.remote
+    }
+
+    /// Parses a history document and upgrades it to `SCHEMA_VERSION`.
+    pub fn from_json(content: &str) -> Result<Self> {
         // Use from_str directly to propagate deserialization errors
-        let storage = serde_json::from_str(&content)
+        let mut storage: Self = serde_json::from_str(content)
             .context("Failed to parse storage JSON")?;
+        storage.migrate();
         Ok(storage)
     }
 
-    pub fn save(&self) -> Result<()> {
-        let path = get_data_path()?;
-        if let Some(parent) = path.parent() {
-            fs::create_dir_all(parent)?;
+    /// Brings an older document up to `SCHEMA_VERSION`. Returns true if
+    /// anything changed, so callers can decide whether to persist it.
+    pub fn migrate(&mut self) -> bool {
+        if self.schema_version >= SCHEMA_VERSION {
+            return false;
         }
-        
-        // Serialize first (before acquiring lock)
-        let content = serde_json::to_string_pretty(self)
-            .context("Failed to serialize storage")?;
-        
-        // Write to temporary file first (atomic operation)
-        let temp_path = path.with_extension("json.tmp");
-        
-        // Create/open file with exclusive lock for writing
-        let file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&temp_path)
-            .context("Failed to create temporary storage file")?;
-        
-        // Acquire exclusive lock
-        file.lock_exclusive()
-            .context("Failed to acquire exclusive lock on storage file")?;
-        
-        let mut file_writer = file;
-        file_writer.write_all(content.as_bytes())
-            .context("Failed to write to temporary storage file")?;
-        
-        // Ensure data is flushed to disk
-        file_writer.sync_all()
-            .context("Failed to sync temporary storage file")?;
-        
-        // Lock is automatically released when file goes out of scope
-        drop(file_writer);
-        
-        // Atomic rename (this is atomic on most filesystems)
-        fs::rename(&temp_path, &path)
-            .context("Failed to rename temporary storage file")?;
-        
+
+        // v0 -> v1: `kind` became `ClipKind`. The legacy "text"/"image"
+        // strings deserialize directly, so only the version is stamped.
+
+        // v1 -> v2: every item gets a hash so backends can address it
+        if self.schema_version < 2 {
+            for item in &mut self.history {
+                if item.hash.is_none() {
+                    item.hash = Some(content_hash(&item.content));
+                }
+            }
+        }
+
+        // v2 -> v3: text items record the language they are written in
+        if self.schema_version < 3 {
+            for item in &mut self.history {
+                item.language = analyze(item.kind, &item.content).0;
+            }
+        }
+
+        // v3 -> v4: text items record their category
+        if self.schema_version < 4 {
+            for item in &mut self.history {
+                item.category = analyze(item.kind, &item.content).1;
+            }
+        }
+
+        self.schema_version = SCHEMA_VERSION;
+        true
+    }
+
+    /// Persists queued changes through the configured backend. The queue
+    /// is only drained on success so a failed save can be retried.
+    pub fn save(&mut self) -> Result<()> {
+        if self.remote {
+            ipc::DaemonClient::connect()?.apply(&self.pending)?;
+            self.pending.clear();
+            // The daemon deletes the files along with its own copies
+            self.released.clear();
+            return Ok(());
+        }
+        let mut backend = backend::open(self.config())?;
+        backend.persist(self, &self.pending)?;
+        self.pending.clear();
+        self.delete_released();
         Ok(())
     }
 
+    // Only after the save: until then the items may still be loaded from
+    // disk. A file another item points at again stays.
+    fn delete_released(&mut self) {
+        let released = std::mem::take(&mut self.released);
+        if released.is_empty() {
+            return;
+        }
+        let Ok(blobs) = BlobStore::open() else { return };
+        for path in released {
+            if !self.history.iter().any(|item| item.image_path() == Some(path.as_path())) {
+                blobs.remove(&path);
+            }
+        }
+    }
+
+    /// Picks up changes other processes have saved since this storage was
+    /// loaded or last synced. Call it after `save`, not with unsaved edits.
+    pub fn sync(&mut self) -> Result<()> {
+        if self.remote {
+            self.history = ipc::DaemonClient::connect()?.list()?;
+            return Ok(());
+        }
+        let mut backend = backend::open(self.config())?;
+        backend.sync(self)
+    }
+
+    /// Applies an already-persisted event to the in-memory history without
+    /// queueing it again. Replaying an event twice is harmless.
+    pub fn apply(&mut self, event: &StorageEvent) {
+        match event {
+            StorageEvent::Added(item) => {
+                self.history.retain(|i| i.id() != item.id());
+                self.history.insert(0, item.clone());
+            }
+            StorageEvent::Pinned(hash) | StorageEvent::Unpinned(hash) => {
+                let pinned = matches!(event, StorageEvent::Pinned(_));
+                if let Some(item) = self.history.iter_mut().find(|i| i.id() == hash) {
+                    item.pinned = pinned;
+                }
+            }
+            StorageEvent::Deleted(hash) => self.history.retain(|i| i.id() != hash),
+            StorageEvent::Cleared { keep_pinned: true } => self.history.retain(|i| i.pinned),
+            StorageEvent::Cleared { keep_pinned: false } => self.history.clear(),
+        }
+    }
+
+    /// Changes made since the last successful `save`.
+    pub fn pending(&self) -> &[StorageEvent] {
+        &self.pending
+    }
+
     pub fn get_images_dir() -> Result<PathBuf> {
         let proj_dirs = ProjectDirs::from("com", "asthetic", "clipboard")
             .context("Could not determine project directories")?;
@@ -157,98 +526,256 @@ impl ClipboardStorage {
         Ok(dir)
     }
 
-    pub fn add(&mut self, content: String, kind: String, hash: Option<String>, max_history: usize) {
+    pub fn add(&mut self, content: String, kind: ClipKind, hash: Option<String>, max_history: usize) {
+        self.add_with_representations(content, kind, hash, Representations::new(), max_history);
+    }
+
+    /// Like `add`, also recording the other formats the clip was offered in.
+    pub fn add_with_representations(
+        &mut self,
+        content: String,
+        kind: ClipKind,
+        hash: Option<String>,
+        representations: Representations,
+        max_history: usize,
+    ) {
+        let meta = ClipMeta { representations, ..Default::default() };
+        self.add_with_meta(content, kind, hash, meta, max_history);
+    }
+
+    /// Like `add`, also recording where the clip came from, its other
+    /// formats and when it expires.
+    pub fn add_with_meta(
+        &mut self,
+        content: String,
+        kind: ClipKind,
+        hash: Option<String>,
+        meta: ClipMeta,
+        max_history: usize,
+    ) {
+        let ClipMeta { representations, expires_at, source, sensitive, image } = meta;
         // Check if item already exists to preserve timestamp
         let mut timestamp = Local::now();
-        
-        if kind == "text" {
-            // Remove existing identical item
-            if let Some(pos) = self.history.iter().position(|item| item.content == content) {
-                 timestamp = self.history[pos].timestamp;
-                 self.history.remove(pos);
+        let hash = hash.unwrap_or_else(|| clip_hash(&content, &representations));
+
+        let existing = match kind {
+            // Binary payloads are deduplicated by hash, everything else by content
+            ClipKind::Image => self.history.iter().position(|item| item.id() == hash),
+            _ => self.history.iter().position(|item| item.kind == kind && item.content == content),
+        };
+        if let Some(pos) = existing {
+            timestamp = self.history[pos].timestamp;
+            let old = self.history.remove(pos);
+            // Same text in different formats replaces the old entry
+            if old.id() != hash {
+                self.pending.push(StorageEvent::Deleted(old.id().to_string()));
             }
-        } else if kind == "image" {
-            // Deduplicate by hash if available
-             if let Some(ref h) = hash {
-                 if let Some(pos) = self.history.iter().position(|item| item.hash.as_ref() == Some(h)) {
-                     timestamp = self.history[pos].timestamp;
-                     self.history.remove(pos);
-                 }
-             }
         }
-        
+
         // Add new item to front
-        self.history.insert(0, HistoryItem {
+        let (language, category) = analyze(kind, &content);
+        let item = HistoryItem {
+            language,
+            category,
             content,
             timestamp,
             pinned: false,
             kind,
-            hash,
+            hash: Some(hash),
+            representations,
+            expires_at,
+            source,
+            sensitive,
+            image,
+        };
+        self.pending.push(StorageEvent::Added(item.clone()));
+        self.history.insert(0, item);
+
+        // Limit history size. Pinned items don't count towards the limit,
+        // so keep all of them plus the newest `max_history` unpinned ones,
+        // preserving the original interleaving.
+        let mut unpinned_kept = 0;
+        let (pending, released) = (&mut self.pending, &mut self.released);
+        self.history.retain(|item| {
+            if item.pinned {
+                return true;
+            }
+            unpinned_kept += 1;
+            if unpinned_kept > max_history {
+                forget(pending, released, item);
+                return false;
+            }
+            true
         });
+    }
 
-        // Limit history size
-        if self.history.len() > max_history {
-            // Keep pinned items, remove oldest unpinned
-            let pinned: Vec<HistoryItem> = self.history.iter().filter(|i| i.pinned).cloned().collect();
-            let mut unpinned: Vec<HistoryItem> = self.history.iter().filter(|i| !i.pinned).cloned().collect();
-            
-            // Trim unpinned
-            let space_for_unpinned = if max_history > pinned.len() { max_history - pinned.len() } else { 0 };
-            unpinned.truncate(space_for_unpinned);
-            
-            let mut new_history: Vec<HistoryItem> = Vec::new();
-            
-            // We want to reconstruct the history list preserving the original order (newest first)
-            // but only keeping items that survived the truncation (pinned + top unpinned).
-            
-            // Optimization: unpinned now contains the truncated list of unpinned items we want to keep.
-            // Pinned contains all pinned items.
-            // We can just iterate the original list again and keep items if they are in our 'keep' sets.
-            // But checking existence is slow. A better way is:
-            // Since we just want valid items, and the order in `pinned` and `unpinned` is already correct 
-            // relative to their own groups (filter preserves order), we just need to merge them back 
-            // respecting original relative order.
-            
-            // Actually, simplest correct way that preserves exact original interleaving:
-            let mut unpinned_remaining = space_for_unpinned;
-            for item in &self.history {
-                if item.pinned {
-                    new_history.push(item.clone());
-                } else if unpinned_remaining > 0 {
-                    new_history.push(item.clone());
-                    unpinned_remaining -= 1;
-                }
+    /// Deletes unpinned items whose `expires_at` is before `now`. Returns
+    /// how many went.
+    pub fn remove_expired(&mut self, now: DateTime<Local>) -> usize {
+        let before = self.history.len();
+        let (pending, released) = (&mut self.pending, &mut self.released);
+        self.history.retain(|item| {
+            let expired = !item.pinned && item.expires_at.is_some_and(|at| at <= now);
+            if expired {
+                forget(pending, released, item);
             }
-            // self.history = new_history; // This is set below loop
-             // Reconstruct preserved history 
-             // Order matters: we want to keep the original order.
-             // Simplest way: iterate original, keep if pinned OR if it is in the allowed unpinned set.
-             // But 'unpinned' vec contains the ones we want to keep.
-             
-            let _unpinned_hashes: std::collections::HashSet<String> = unpinned.iter().map(|i| i.content.clone() /* content is unique-ish for text, but images? hash? */).collect();
-            // Wait, this is getting complex.
-            // Let's stick to previous logic but parameterized.
-            
-            let mut new_history = Vec::new();
-            let mut count = 0;
-            
-             for item in &self.history {
-                if item.pinned || count < space_for_unpinned {
-                    new_history.push(item.clone());
-                    if !item.pinned {
-                        count += 1;
-                    }
+            !expired
+        });
+        before - self.history.len()
+    }
+
+    /// Deletes unpinned items older than `rules` allow, then the oldest
+    /// unpinned images until the rest fit in the image budget. Returns the
+    /// items that went.
+    pub fn apply_retention(&mut self, rules: &RetentionConfig, now: DateTime<Local>) -> Vec<HistoryItem> {
+        let max_age = rules.max_age_days.map(|days| TimeDelta::days(days.into()));
+        let sensitive_max_age = rules.sensitive_max_age_mins.map(|mins| TimeDelta::minutes(mins.into()));
+        let mut doomed: HashSet<String> = self.history
+            .iter()
+            .filter(|item| !item.pinned)
+            .filter(|item| {
+                let age = now - item.timestamp;
+                max_age.is_some_and(|max| age > max)
+                    || (item.sensitive && sensitive_max_age.is_some_and(|max| age > max))
+            })
+            .map(|item| item.id().to_string())
+            .collect();
+
+        if let Some(max_mb) = rules.max_images_mb {
+            let size = |item: &HistoryItem| fs::metadata(&item.content).map(|m| m.len()).unwrap_or(0);
+            let images = || self.history.iter().filter(|item| item.kind == ClipKind::Image && !doomed.contains(item.id()));
+            // Pinned images stay whatever they take up
+            let mut used: u64 = images().filter(|item| item.pinned).map(size).sum();
+            let mut over_budget = Vec::new();
+            // History is newest first, so the oldest are the ones that don't fit
+            for item in images().filter(|item| !item.pinned) {
+                used += size(item);
+                if used > max_mb * 1024 * 1024 {
+                    over_budget.push(item.id().to_string());
                 }
             }
-            self.history = new_history;
+            doomed.extend(over_budget);
+        }
+
+        let (removed, kept) = std::mem::take(&mut self.history)
+            .into_iter()
+            .partition(|item| doomed.contains(item.id()));
+        self.history = kept;
+        for item in &removed {
+            forget(&mut self.pending, &mut self.released, item);
+        }
+        removed
+    }
+
+    /// Brings the blob store and the history back in line: deletes files
+    /// and thumbnails no item refers to, and items whose file has gone
+    /// missing. Files younger than `grace` are left alone, as the daemon
+    /// writes an image just before recording it.
+    pub fn collect_garbage(&mut self, blobs: &BlobStore, grace: Duration) -> Result<GcReport> {
+        let mut report = GcReport::default();
+        let referenced: HashSet<&Path> = self.history.iter().filter_map(HistoryItem::image_path).collect();
+        for entry in fs::read_dir(blobs.dir())? {
+            let entry = entry?;
+            let path = entry.path();
+            let metadata = entry.metadata()?;
+            let young = metadata.modified()?.elapsed().is_ok_and(|age| age < grace);
+            if !metadata.is_file() || young || referenced.contains(path.as_path()) {
+                continue;
+            }
+            report.orphans += 1;
+            report.bytes_freed += blobs.remove(&path);
+        }
+
+        let pending = &mut self.pending;
+        self.history.retain(|item| {
+            let missing = item.image_path().is_some_and(|path| !path.exists());
+            if missing {
+                pending.push(StorageEvent::Deleted(item.id().to_string()));
+                report.missing += 1;
+            }
+            !missing
+        });
+        let images: HashSet<&str> = self.history.iter()
+            .filter(|item| item.kind == ClipKind::Image)
+            .map(HistoryItem::id)
+            .collect();
+        report.bytes_freed += blobs.prune_thumbnails(&images);
+        Ok(report)
+    }
+
+    /// Index of the item with the given id.
+    pub fn position(&self, hash: &str) -> Option<usize> {
+        self.history.iter().position(|i| i.id() == hash)
+    }
+
+    /// Flips the pin state of the item at `index`.
+    pub fn toggle_pin(&mut self, index: usize) {
+        if let Some(item) = self.history.get_mut(index) {
+            item.pinned = !item.pinned;
+            let id = item.id().to_string();
+            self.pending.push(if item.pinned {
+                StorageEvent::Pinned(id)
+            } else {
+                StorageEvent::Unpinned(id)
+            });
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<HistoryItem> {
+        if index >= self.history.len() {
+            return None;
+        }
+        let item = self.history.remove(index);
+        forget(&mut self.pending, &mut self.released, &item);
+        Some(item)
+    }
+
+    /// Removes every item, or only the unpinned ones if `keep_pinned`.
+    pub fn clear(&mut self, keep_pinned: bool) {
+        let (kept, removed): (Vec<_>, Vec<_>) = std::mem::take(&mut self.history)
+            .into_iter()
+            .partition(|i| keep_pinned && i.pinned);
+        self.history = kept;
+        self.released.extend(removed.iter().filter_map(HistoryItem::image_path).map(Path::to_path_buf));
+        self.pending.push(StorageEvent::Cleared { keep_pinned });
+    }
+}
+
+/// What `ClipboardStorage::collect_garbage` cleaned up.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct GcReport {
+    /// Image files no item referred to
+    pub orphans: usize,
+    /// Including thumbnails
+    pub bytes_freed: u64,
+    /// Image items whose file was gone
+    pub missing: usize,
+}
+
+// Queues the deletion of `item`, and of its image file once that is saved
+fn forget(pending: &mut Vec<StorageEvent>, released: &mut Vec<PathBuf>, item: &HistoryItem) {
+    pending.push(StorageEvent::Deleted(item.id().to_string()));
+    if let Some(path) = item.image_path() {
+        released.push(path.to_path_buf());
+    }
+}
+
+/// Language and category of a new item. Only plain text is looked at;
+/// other kinds have their own preview.
+fn analyze(kind: ClipKind, content: &str) -> (Option<Language>, Option<Category>) {
+    match kind {
+        ClipKind::Text => {
+            let language = highlight::detect(content);
+            (language, category::classify(content, language))
         }
+        _ => (None, None),
     }
 }
 
-fn get_data_path() -> Result<PathBuf> {
+pub(crate) fn get_data_dir() -> Result<PathBuf> {
     let proj_dirs = ProjectDirs::from("com", "asthetic", "clipboard")
         .context("Could not determine project directories")?;
-    Ok(proj_dirs.data_dir().join("history.json"))
+    Ok(proj_dirs.data_dir().to_path_buf())
 }
 
 fn get_config_path() -> Result<PathBuf> {
@@ -259,6 +786,16 @@ fn get_config_path() -> Result<PathBuf> {
     Ok(proj_dirs.config_dir().join("config.json"))
 }
 
+/// An empty scratch directory for the test `name`, unique to this run.
+/// Tests remove it again when they are done.
+#[cfg(test)]
+pub(crate) fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("asthetic-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -267,7 +804,7 @@ mod tests {
     fn test_add_and_limit() {
         let mut storage = ClipboardStorage::default();
         for i in 0..60 {
-            storage.add(format!("content {}", i), "text".to_string(), None, 50);
+            storage.add(format!("content {}", i), ClipKind::Text, None, 50);
         }
         // Should be capped at 50 (unpinned)
         assert_eq!(storage.history.len(), 50);
@@ -275,17 +812,104 @@ mod tests {
         assert_eq!(storage.history[0].content, "content 59");
     }
 
+    #[test]
+    fn test_age_retention() {
+        let now = Local::now();
+        let mut storage = ClipboardStorage::default();
+        for (content, days) in [("ancient", 40), ("old pinned", 40), ("recent", 1)] {
+            storage.add(content.to_string(), ClipKind::Text, None, 50);
+            storage.history[0].timestamp = now - TimeDelta::days(days);
+        }
+        storage.toggle_pin(1);
+        let meta = ClipMeta { sensitive: true, ..Default::default() };
+        storage.add_with_meta("tok_••••••••".to_string(), ClipKind::Text, None, meta, 50);
+        storage.history[0].timestamp = now - TimeDelta::minutes(20);
+
+        // No rules, nothing goes
+        assert!(storage.apply_retention(&RetentionConfig::default(), now).is_empty());
+
+        let rules = RetentionConfig {
+            max_age_days: Some(30),
+            sensitive_max_age_mins: Some(10),
+            ..Default::default()
+        };
+        let removed: Vec<String> = storage.apply_retention(&rules, now).into_iter().map(|i| i.content).collect();
+        assert_eq!(removed, vec!["tok_••••••••", "ancient"]);
+        let contents: Vec<&str> = storage.history.iter().map(|i| i.content.as_str()).collect();
+        assert_eq!(contents, vec!["recent", "old pinned"]);
+        assert!(matches!(storage.pending().last(), Some(StorageEvent::Deleted(_))));
+    }
+
+    #[test]
+    fn test_backend() {
+        let mut config = AppConfig { storage_backend: BackendKind::Journal, ..Default::default() };
+        assert_eq!(config.backend(), BackendKind::Journal);
+        // An encrypted history stays where encryption put it
+        config.encryption.enabled = true;
+        assert_eq!(config.backend(), BackendKind::Json);
+    }
+
+    #[test]
+    fn test_image_budget() {
+        let dir = test_dir("budget");
+        let mut storage = ClipboardStorage::default();
+        for name in ["a", "b", "c", "d"] {
+            let path = dir.join(format!("{}.png", name));
+            fs::write(&path, vec![0u8; 400 * 1024]).unwrap();
+            storage.add(path.to_string_lossy().to_string(), ClipKind::Image, Some(name.to_string()), 50);
+        }
+        // The oldest image is pinned; it counts but stays
+        storage.toggle_pin(3);
+
+        let rules = RetentionConfig { max_images_mb: Some(1), ..Default::default() };
+        let removed: Vec<String> = storage.apply_retention(&rules, Local::now())
+            .iter()
+            .map(|i| i.id().to_string())
+            .collect();
+        assert_eq!(removed, vec!["c", "b"]);
+        let ids: Vec<&str> = storage.history.iter().map(|i| i.id()).collect();
+        assert_eq!(ids, vec!["d", "a"]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_collect_garbage() {
+        let dir = test_dir("gc");
+        let mut storage = ClipboardStorage::default();
+        for name in ["kept", "orphan"] {
+            fs::write(dir.join(format!("{}.png", name)), vec![0u8; 100]).unwrap();
+        }
+        let kept = dir.join("kept.png").to_string_lossy().to_string();
+        let missing = dir.join("missing.png").to_string_lossy().to_string();
+        storage.add(kept, ClipKind::Image, Some("kept".to_string()), 50);
+        storage.add(missing, ClipKind::Image, Some("missing".to_string()), 50);
+
+        let blobs = BlobStore::new(dir.clone(), dir.join("thumbnails"));
+        let report = storage.collect_garbage(&blobs, Duration::ZERO).unwrap();
+        assert_eq!(report, GcReport { orphans: 1, bytes_freed: 100, missing: 1 });
+        assert!(!dir.join("orphan.png").exists());
+        let ids: Vec<&str> = storage.history.iter().map(|i| i.id()).collect();
+        assert_eq!(ids, vec!["kept"]);
+
+        // Removing the item releases its file for deletion after the save
+        storage.remove(0);
+        assert_eq!(storage.released, vec![dir.join("kept.png")]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
     #[test]
     fn test_pinning_logic() {
         let mut storage = ClipboardStorage::default();
         
         // Add item and pin it
-        storage.add("pinned item".to_string(), "text".to_string(), None, 50);
+        storage.add("pinned item".to_string(), ClipKind::Text, None, 50);
         storage.history[0].pinned = true;
         
         // Add 60 more items
         for i in 0..60 {
-            storage.add(format!("content {}", i), "text".to_string(), None, 50);
+            storage.add(format!("content {}", i), ClipKind::Text, None, 50);
         }
 
         // Pinned item should still be there
@@ -299,4 +923,155 @@ mod tests {
         assert_eq!(count_pinned, 1);
         assert_eq!(count_unpinned, 50);
     }
+
+    #[test]
+    fn test_migrate_legacy_history() {
+        let legacy = r#"{
+            "history": [
+                {"content": "hello", "timestamp": "2024-01-01T10:00:00+00:00"},
+                {"content": "/tmp/a.png", "timestamp": "2024-01-01T09:00:00+00:00", "kind": "image", "hash": "abc"}
+            ]
+        }"#;
+        let storage = ClipboardStorage::from_json(legacy).unwrap();
+
+        assert_eq!(storage.schema_version, SCHEMA_VERSION);
+        assert_eq!(storage.history[0].kind, ClipKind::Text);
+        assert_eq!(storage.history[1].kind, ClipKind::Image);
+
+        // Every item is addressable by hash after migration
+        assert_eq!(storage.history[0].id(), content_hash("hello"));
+        assert_eq!(storage.history[0].language, None);
+
+        // Kinds keep their legacy spelling on disk
+        let json = serde_json::to_string(&storage).unwrap();
+        assert!(json.contains(r#""kind":"image""#));
+    }
+
+    #[test]
+    fn test_mutations_queue_events() {
+        let mut storage = ClipboardStorage::default();
+        storage.add("a".to_string(), ClipKind::Text, None, 2);
+        storage.add("b".to_string(), ClipKind::Text, None, 2);
+        storage.add("c".to_string(), ClipKind::Text, None, 2);
+        storage.toggle_pin(0);
+        storage.remove(1);
+
+        let events = storage.pending();
+        // Adding "c" evicted "a"
+        assert!(matches!(&events[3], StorageEvent::Deleted(h) if *h == content_hash("a")));
+        assert!(matches!(&events[4], StorageEvent::Pinned(h) if *h == content_hash("c")));
+        assert!(matches!(&events[5], StorageEvent::Deleted(h) if *h == content_hash("b")));
+        assert_eq!(storage.history.len(), 1);
+    }
+
+    #[test]
+    fn test_representations_replace_plain_copy() {
+        let mut storage = ClipboardStorage::default();
+        storage.add("hello".to_string(), ClipKind::Text, None, 50);
+
+        let mut representations = Representations::new();
+        representations.insert(mime::TEXT_HTML.to_string(), "<b>hello</b>".to_string());
+        storage.add_with_representations("hello".to_string(), ClipKind::Html, None, representations.clone(), 50);
+        // Kinds differ, so both are kept, under distinct ids
+        assert_eq!(storage.history.len(), 2);
+        assert_ne!(storage.history[0].id(), storage.history[1].id());
+
+        // Copying it again with different markup updates the entry in place
+        representations.insert(mime::TEXT_HTML.to_string(), "<i>hello</i>".to_string());
+        storage.add_with_representations("hello".to_string(), ClipKind::Html, None, representations, 50);
+        assert_eq!(storage.history.len(), 2);
+        assert_eq!(storage.history[0].representations[mime::TEXT_HTML], "<i>hello</i>");
+        let events = storage.pending();
+        assert!(matches!(&events[events.len() - 2], StorageEvent::Deleted(_)));
+
+        let json = serde_json::to_string(&storage).unwrap();
+        assert_eq!(json.matches("representations").count(), 1);
+    }
+
+    #[test]
+    fn test_expiring_items() {
+        let mut storage = ClipboardStorage::default();
+        let soon = Local::now() + chrono::TimeDelta::minutes(5);
+        let meta = ClipMeta { expires_at: Some(soon), ..Default::default() };
+        storage.add_with_meta("secret".to_string(), ClipKind::Text, None, meta.clone(), 50);
+        storage.add_with_meta("pinned secret".to_string(), ClipKind::Text, None, meta, 50);
+        storage.toggle_pin(0);
+        storage.add("plain".to_string(), ClipKind::Text, None, 50);
+
+        assert_eq!(storage.remove_expired(Local::now()), 0);
+        assert_eq!(storage.remove_expired(soon), 1);
+        let contents: Vec<&str> = storage.history.iter().map(|i| i.content.as_str()).collect();
+        assert_eq!(contents, vec!["plain", "pinned secret"]);
+        assert!(matches!(storage.pending().last(), Some(StorageEvent::Deleted(h)) if *h == content_hash("secret")));
+    }
+
+    #[test]
+    fn test_source_filter() {
+        let mut storage = ClipboardStorage::default();
+        let source = SourceApp {
+            class: "org.gnome.Terminal".to_string(),
+            instance: "gnome-terminal".to_string(),
+            title: Some("~/src".to_string()),
+        };
+        let meta = ClipMeta { source: Some(source), ..Default::default() };
+        storage.add_with_meta("ls -la".to_string(), ClipKind::Text, None, meta, 50);
+        storage.add("git status".to_string(), ClipKind::Text, None, 50);
+
+        let count = |query: &str| storage.search(&SearchQuery::parse(query)).len();
+        // `app:` matches part of the source app's class or instance
+        assert_eq!(count("app:terminal"), 1);
+        assert_eq!(count("app:Terminal 'ls"), 1);
+        assert_eq!(count("app:terminal git"), 0);
+        assert_eq!(count("app:firefox"), 0);
+    }
+
+    #[test]
+    fn test_private_mode() {
+        let now = Local::now();
+        let mut config = AppConfig::default();
+        assert!(!config.is_private(now));
+        config.private_mode = true;
+        assert!(config.is_private(now));
+        config.private_until = Some(now + chrono::TimeDelta::minutes(15));
+        assert!(config.is_private(now));
+        assert!(!config.is_private(now + chrono::TimeDelta::minutes(15)));
+    }
+
+    #[test]
+    fn test_search_modes() {
+        let mut storage = ClipboardStorage::default();
+        storage.add("git status".to_string(), ClipKind::Text, None, 50);
+        storage.add("Grep the logs".to_string(), ClipKind::Text, None, 50);
+        storage.add("/tmp/grep.png".to_string(), ClipKind::Image, Some("img".to_string()), 50);
+        storage.add("cargo test".to_string(), ClipKind::Text, None, 50);
+
+        let contents = |query: &str| -> Vec<String> {
+            storage.search(&SearchQuery::parse(query))
+                .iter()
+                .map(|hit| storage.history[hit.index].content.clone())
+                .collect()
+        };
+
+        // Empty query lists everything, images included
+        assert_eq!(contents("").len(), 4);
+        // Case-insensitive, images skipped
+        assert_eq!(contents("'grep"), vec!["Grep the logs"]);
+        // Fuzzy: matches at word starts rank first
+        assert_eq!(contents("gt"), vec!["Grep the logs", "cargo test", "git status"]);
+        assert!(contents("'gt").is_empty());
+
+        // `lang:` keeps only snippets detected in that language
+        assert_eq!(contents("lang:sh"), vec!["cargo test", "git status"]);
+        assert_eq!(contents("lang:shell 'git"), vec!["git status"]);
+        assert!(contents("lang:rust").is_empty());
+        assert_eq!(contents("is:code lang:sh car"), vec!["cargo test"]);
+        assert_eq!(contents("lang:sh is:url"), Vec::<String>::new());
+
+        // Pinning lifts an otherwise weaker match
+        let pos = storage.history.iter().position(|i| i.content == "git status").unwrap();
+        storage.toggle_pin(pos);
+        let first = &storage.search(&SearchQuery::parse("gt"))[0];
+        assert_eq!(storage.history[first.index].content, "git status");
+        assert_eq!(first.positions, vec![0, 2]);
+    }
 }
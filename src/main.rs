@@ -2,11 +2,26 @@ use gtk4::prelude::*;
 use gtk4::{Application, ApplicationWindow, Button, Label, ListBox, ListBoxRow, ScrolledWindow, Orientation, PolicyType, Align};
 use std::rc::Rc;
 use std::cell::RefCell;
-use asthetic_clipboard::{ClipboardStorage, AppConfig};
+use std::collections::{HashMap, HashSet};
+use chrono::Local;
+use asthetic_clipboard::{ClipboardStorage, AppConfig, ClipKind, HistoryItem, SearchQuery};
+use asthetic_clipboard::blob::{BlobStore, ThumbnailSize};
+use asthetic_clipboard::category::{self, Category};
+use asthetic_clipboard::clipboard;
+use asthetic_clipboard::crypto::{self, EncryptionConfig, KeySource};
+use asthetic_clipboard::highlight::{self, Language, Token};
+use asthetic_clipboard::ipc::{self, Change};
+use asthetic_clipboard::search::highlight_runs;
+use asthetic_clipboard::source::SourceApp;
 
 const APP_ID: &str = "com.asthetic.clipboard";
+const MAX_FILES_SHOWN: usize = 5;
+// How long "Pause for 15 Minutes" pauses recording for
+const PRIVATE_PAUSE_MINUTES: i64 = 15;
 
 fn main() {
+    clipboard::run_helper_if_requested();
+
     let app = Application::builder().application_id(APP_ID).build();
 
     app.connect_activate(|app| {
@@ -37,7 +52,12 @@ fn build_ui(app: &Application) {
 
     // Data Dependencies (Initialize Early)
     let menu_counter = Rc::new(RefCell::new(0));
-    let storage = Rc::new(RefCell::new(ClipboardStorage::load().unwrap_or_default()));
+    let search_query = Rc::new(RefCell::new(String::new()));
+    let (loaded, locked) = match ClipboardStorage::load_shared() {
+        Ok(storage) => (storage, false),
+        Err(e) => (ClipboardStorage::default(), crypto::is_locked_error(&e)),
+    };
+    let storage = Rc::new(RefCell::new(loaded));
     let config = Rc::new(RefCell::new(AppConfig::load().unwrap_or_default()));
     let list_box = Rc::new(ListBox::new());
     list_box.add_css_class("content-list");
@@ -112,6 +132,40 @@ fn build_ui(app: &Application) {
     start_box.append(&start_switch);
     settings_box.append(&start_box);
 
+    // 4. Private Mode: nothing is recorded while it is on
+    let private_box = gtk4::Box::new(Orientation::Horizontal, 10);
+    let private_label = Label::new(Some("Private Mode"));
+    let private_switch = gtk4::Switch::new();
+    private_box.append(&private_label);
+    private_box.append(&private_switch);
+    settings_box.append(&private_box);
+
+    let pause_btn = Button::with_label("Pause for 15 Minutes");
+    pause_btn.add_css_class("menu-button");
+    settings_box.append(&pause_btn);
+
+    // Shown in the header while private mode is on
+    let private_indicator = gtk4::Image::from_icon_name("view-conceal-symbolic");
+    private_indicator.add_css_class("private-indicator");
+    show_private_mode(&config.borrow(), &private_switch, &private_indicator);
+
+    let config_private = config.clone();
+    let indicator_private = private_indicator.clone();
+    private_switch.connect_state_set(move |switch, state| {
+        // Also fires when the pause button or an expired pause flips it
+        if state != config_private.borrow().is_private(Local::now()) {
+            set_private_mode(&config_private, switch, &indicator_private, state, None);
+        }
+        glib::Propagation::Proceed
+    });
+    let config_pause = config.clone();
+    let switch_pause = private_switch.clone();
+    let indicator_pause = private_indicator.clone();
+    pause_btn.connect_clicked(move |_| {
+        let duration = chrono::TimeDelta::minutes(PRIVATE_PAUSE_MINUTES);
+        set_private_mode(&config_pause, &switch_pause, &indicator_pause, true, Some(duration));
+    });
+
     let sep = gtk4::Separator::new(Orientation::Horizontal);
     settings_box.append(&sep);
 
@@ -123,13 +177,14 @@ fn build_ui(app: &Application) {
     let list_box_clear_unpinned = list_box.clone();
     let window_clear_unpinned = window.clone();
     let menu_counter_clear_unpinned = menu_counter.clone();
+    let query_clear_unpinned = search_query.clone();
     clear_unpinned_btn.connect_clicked(move |_| {
         {
             let mut s = storage_clear_unpinned.borrow_mut();
-            s.history.retain(|i| i.pinned);
+            s.clear(true);
             let _ = s.save();
         }
-        refresh_list(&list_box_clear_unpinned, &storage_clear_unpinned.borrow(), &window_clear_unpinned, storage_clear_unpinned.clone(), menu_counter_clear_unpinned.clone());
+        refresh_list(&list_box_clear_unpinned, &storage_clear_unpinned.borrow(), &window_clear_unpinned, storage_clear_unpinned.clone(), menu_counter_clear_unpinned.clone(), query_clear_unpinned.clone());
     });
     settings_box.append(&clear_unpinned_btn);
 
@@ -141,13 +196,14 @@ fn build_ui(app: &Application) {
     let list_box_clear_all = list_box.clone();
     let window_clear_all = window.clone();
     let menu_counter_clear_all = menu_counter.clone();
+    let query_clear_all = search_query.clone();
     clear_all_btn.connect_clicked(move |_| {
         {
             let mut s = storage_clear_all.borrow_mut();
-            s.history.clear();
+            s.clear(false);
             let _ = s.save();
         }
-        refresh_list(&list_box_clear_all, &storage_clear_all.borrow(), &window_clear_all, storage_clear_all.clone(), menu_counter_clear_all.clone());
+        refresh_list(&list_box_clear_all, &storage_clear_all.borrow(), &window_clear_all, storage_clear_all.clone(), menu_counter_clear_all.clone(), query_clear_all.clone());
     });
     settings_box.append(&clear_all_btn);
 
@@ -163,6 +219,25 @@ fn build_ui(app: &Application) {
 
     // Add Settings Button to Header (Left)
     header_bar.pack_start(&settings_btn);
+    header_bar.pack_end(&private_indicator);
+
+    // Search Entry (Center): filters the list as you type
+    let search_entry = gtk4::SearchEntry::new();
+    search_entry.set_placeholder_text(Some("Search ('exact, re:regex, is:url, lang:rust, app:firefox)"));
+    search_entry.set_hexpand(true);
+    header_bar.set_title_widget(Some(&search_entry));
+
+    let storage_search = storage.clone();
+    let list_box_search = list_box.clone();
+    let window_search = window.clone();
+    let menu_counter_search = menu_counter.clone();
+    let query_search = search_query.clone();
+    search_entry.connect_search_changed(move |entry| {
+        *query_search.borrow_mut() = entry.text().to_string();
+        refresh_list(&list_box_search, &storage_search.borrow(), &window_search, storage_search.clone(), menu_counter_search.clone(), query_search.clone());
+    });
+    // Typing anywhere in the window goes to the search entry
+    search_entry.set_key_capture_widget(Some(&window));
 
     window.set_titlebar(Some(&header_bar));
 
@@ -195,6 +270,24 @@ fn build_ui(app: &Application) {
     let vbox = gtk4::Box::new(Orientation::Vertical, 5);
     window.set_child(Some(&vbox));
 
+    // Encrypted history whose key isn't available yet: ask for it
+    if locked {
+        let unlock_bar = unlock_bar(config.borrow().encryption.key_source);
+        let storage_unlock = storage.clone();
+        let list_box_unlock = list_box.clone();
+        let window_unlock = window.clone();
+        let menu_counter_unlock = menu_counter.clone();
+        let query_unlock = search_query.clone();
+        let unlocked = move || {
+            if let Ok(fresh) = ClipboardStorage::load_shared() {
+                *storage_unlock.borrow_mut() = fresh;
+            }
+            refresh_list(&list_box_unlock, &storage_unlock.borrow(), &window_unlock, storage_unlock.clone(), menu_counter_unlock.clone(), query_unlock.clone());
+        };
+        connect_unlock(&unlock_bar, unlocked);
+        vbox.append(&unlock_bar);
+    }
+
     // Scrolled Window for List (using list_box created earlier)
     let scrolled_window = ScrolledWindow::builder()
         .hscrollbar_policy(PolicyType::Never)
@@ -206,7 +299,44 @@ fn build_ui(app: &Application) {
 
     // Footer Removed!
 
-    refresh_list(&list_box, &storage.borrow(), &window, storage.clone(), menu_counter.clone());
+    refresh_list(&list_box, &storage.borrow(), &window, storage.clone(), menu_counter.clone(), search_query.clone());
+
+    // Live updates: apply changes pushed by the daemon while the window is open
+    let changes = ipc::watch();
+    let win_weak_changes = window.downgrade();
+    let storage_changes = storage.clone();
+    let list_box_changes = list_box.clone();
+    let menu_counter_changes = menu_counter.clone();
+    let query_changes = search_query.clone();
+    let config_changes = config.clone();
+    glib::timeout_add_local(std::time::Duration::from_millis(250), move || {
+        let Some(w) = win_weak_changes.upgrade() else {
+            return glib::ControlFlow::Break;
+        };
+        // A timed pause ends on its own
+        show_private_mode(&config_changes.borrow(), &private_switch, &private_indicator);
+        // Rebuilding the list would tear down an open item menu; wait for it to close
+        if *menu_counter_changes.borrow() > 0 {
+            return glib::ControlFlow::Continue;
+        }
+
+        let mut changed = false;
+        while let Ok(change) = changes.try_recv() {
+            match change {
+                Change::Event(event) => storage_changes.borrow_mut().apply(&event),
+                Change::Resync => {
+                    if let Ok(fresh) = ClipboardStorage::load_shared() {
+                        *storage_changes.borrow_mut() = fresh;
+                    }
+                }
+            }
+            changed = true;
+        }
+        if changed {
+            refresh_list(&list_box_changes, &storage_changes.borrow(), &w, storage_changes.clone(), menu_counter_changes.clone(), query_changes.clone());
+        }
+        glib::ControlFlow::Continue
+    });
 
     // Initial CSS Load
     load_css(config.borrow().theme == "dark");
@@ -214,19 +344,344 @@ fn build_ui(app: &Application) {
     window.present();
 }
 
+/// Turns private mode on or off and shows the result.
+fn set_private_mode(
+    config: &Rc<RefCell<AppConfig>>,
+    switch: &gtk4::Switch,
+    indicator: &gtk4::Image,
+    on: bool,
+    duration: Option<chrono::TimeDelta>,
+) {
+    match AppConfig::set_private(on, duration) {
+        Ok(fresh) => *config.borrow_mut() = fresh,
+        Err(e) => eprintln!("Failed to change private mode: {}", e),
+    }
+    show_private_mode(&config.borrow(), switch, indicator);
+}
+
+fn show_private_mode(config: &AppConfig, switch: &gtk4::Switch, indicator: &gtk4::Image) {
+    let private = config.is_private(Local::now());
+    switch.set_active(private);
+    indicator.set_visible(private);
+    let tooltip = match config.private_until {
+        Some(until) => format!("Private mode: not recording until {}", until.format("%H:%M")),
+        None => "Private mode: not recording".to_string(),
+    };
+    indicator.set_tooltip_text(Some(&tooltip));
+}
+
+/// Banner shown in place of the history while it is locked, with a
+/// passphrase entry or, for keyring keys, a retry button.
+fn unlock_bar(key_source: KeySource) -> gtk4::Box {
+    let bar = gtk4::Box::new(Orientation::Vertical, 6);
+    bar.add_css_class("unlock-bar");
+    bar.set_margin_top(10);
+    bar.set_margin_start(10);
+    bar.set_margin_end(10);
+
+    let message = match key_source {
+        KeySource::Passphrase => "History is encrypted. Enter the passphrase to unlock it.",
+        KeySource::Keyring => "History is encrypted and its key couldn't be read from the keyring.",
+    };
+    let label = Label::new(Some(message));
+    label.set_wrap(true);
+    label.set_halign(Align::Start);
+    bar.append(&label);
+
+    match key_source {
+        KeySource::Passphrase => {
+            let entry = gtk4::PasswordEntry::new();
+            entry.set_show_peek_icon(true);
+            entry.set_placeholder_text(Some("Passphrase"));
+            bar.append(&entry);
+        }
+        KeySource::Keyring => bar.append(&Button::with_label("Try Again")),
+    }
+
+    let error_label = Label::new(None);
+    error_label.add_css_class("error");
+    error_label.set_halign(Align::Start);
+    error_label.set_visible(false);
+    bar.append(&error_label);
+    bar
+}
+
+/// Unlocks from `bar`'s entry or button, hiding the bar and calling
+/// `unlocked` on success and showing the error otherwise.
+fn connect_unlock(bar: &gtk4::Box, unlocked: impl Fn() + 'static) {
+    let error_label = bar.last_child().and_downcast::<Label>().expect("unlock bar has an error label");
+    let attempt = move |bar: &gtk4::Box, passphrase: Option<String>| {
+        match crypto::unlock_session(passphrase.as_deref()) {
+            Ok(()) => {
+                bar.set_visible(false);
+                unlocked();
+            }
+            Err(e) => {
+                error_label.set_text(&e.to_string());
+                error_label.set_visible(true);
+            }
+        }
+    };
+
+    let input = error_label.prev_sibling().expect("unlock bar has an input");
+    if let Some(entry) = input.downcast_ref::<gtk4::PasswordEntry>() {
+        let bar = bar.clone();
+        entry.connect_activate(move |entry| {
+            attempt(&bar, Some(entry.text().to_string()));
+            entry.set_text("");
+        });
+    } else if let Some(button) = input.downcast_ref::<Button>() {
+        let bar = bar.clone();
+        button.connect_clicked(move |_| attempt(&bar, None));
+    }
+}
+
+fn show_error(window: &ApplicationWindow, message: &str, error: &anyhow::Error) {
+    gtk4::AlertDialog::builder()
+        .message(message)
+        .detail(format!("{:#}", error))
+        .build()
+        .show(Some(window));
+}
+
+/// Icon, name and size of one copied file.
+fn file_row(path: &std::path::Path) -> gtk4::Box {
+    let row = gtk4::Box::new(Orientation::Horizontal, 6);
+    let metadata = std::fs::metadata(path).ok();
+
+    let icon = match &metadata {
+        Some(m) if m.is_dir() => gtk4::Image::from_icon_name("folder-symbolic"),
+        Some(_) => {
+            let (content_type, _) = gtk4::gio::content_type_guess(Some(path), &[]);
+            gtk4::Image::from_gicon(&gtk4::gio::content_type_get_symbolic_icon(&content_type))
+        }
+        // Moved or deleted since it was copied
+        None => gtk4::Image::from_icon_name("dialog-warning-symbolic"),
+    };
+    icon.set_pixel_size(16);
+    row.append(&icon);
+
+    let name = path.file_name().unwrap_or(path.as_os_str()).to_string_lossy();
+    let name_label = Label::new(Some(&name));
+    name_label.set_halign(Align::Start);
+    name_label.set_hexpand(true);
+    name_label.set_ellipsize(gtk4::pango::EllipsizeMode::Middle);
+    name_label.set_tooltip_text(Some(&path.to_string_lossy()));
+    row.append(&name_label);
+
+    let size_text = match &metadata {
+        Some(m) if m.is_dir() => "Folder".to_string(),
+        Some(m) => gtk4::glib::format_size(m.len()).to_string(),
+        None => "Missing".to_string(),
+    };
+    let size_label = Label::new(Some(&size_text));
+    size_label.add_css_class("timestamp");
+    row.append(&size_label);
+
+    row
+}
+
+/// Icon for a categorised text item: a swatch of the colour itself for
+/// colours, a themed symbol otherwise. Clicking it filters the list to
+/// that category.
+fn category_button(category: Category, content: &str, window: &ApplicationWindow) -> Button {
+    let button = Button::new();
+    button.add_css_class("flat");
+    button.set_valign(Align::Start);
+    button.set_margin_end(6);
+    button.set_tooltip_text(Some(&format!("Show only: {}", category.label())));
+
+    if category == Category::Color {
+        let [r, g, b] = category::parse_color(content).unwrap_or_default();
+        let swatch = gtk4::DrawingArea::new();
+        swatch.set_content_width(16);
+        swatch.set_content_height(16);
+        swatch.set_draw_func(move |_, cr, width, height| {
+            cr.set_source_rgb(r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0);
+            cr.rectangle(0.0, 0.0, width as f64, height as f64);
+            let _ = cr.fill();
+        });
+        button.set_child(Some(&swatch));
+    } else {
+        button.set_icon_name(category_icon_name(category));
+    }
+
+    let window = window.clone();
+    button.connect_clicked(move |_| {
+        add_search_filter(&window, "is", category.as_str());
+    });
+    button
+}
+
+/// Small button naming the app a clip came from, with the window title as
+/// tooltip. Clicking it shows only clips from that app.
+fn source_button(source: &SourceApp, window: &ApplicationWindow) -> Button {
+    let button = Button::with_label(&source.class);
+    button.add_css_class("flat");
+    button.add_css_class("source");
+    button.set_valign(Align::Center);
+    let tooltip = match &source.title {
+        Some(title) => format!("{}\n{}", source.class, title),
+        None => source.class.clone(),
+    };
+    button.set_tooltip_text(Some(&tooltip));
+
+    let window = window.clone();
+    let class = source.class.clone();
+    button.connect_clicked(move |_| {
+        add_search_filter(&window, "app", &class);
+    });
+    button
+}
+
+/// Adds `key:value` to the search. Goes through the search entry so the
+/// filter is visible and editable.
+fn add_search_filter(window: &ApplicationWindow, key: &str, value: &str) {
+    if let Some(entry) = window.titlebar()
+        .and_downcast::<gtk4::HeaderBar>()
+        .and_then(|header| header.title_widget())
+        .and_downcast::<gtk4::SearchEntry>()
+    {
+        entry.set_text(&SearchQuery::with_filter(&entry.text(), key, Some(value)));
+    }
+}
+
+fn category_icon_name(category: Category) -> &'static str {
+    match category {
+        Category::Url => "web-browser-symbolic",
+        Category::Email => "mail-unread-symbolic",
+        Category::Path => "folder-symbolic",
+        Category::Color => "applications-graphics-symbolic",
+        Category::Json | Category::Code => "text-x-script-symbolic",
+        Category::Number => "accessories-calculator-symbolic",
+        Category::Phone => "call-start-symbolic",
+    }
+}
+
+/// Pango markup for `text` with the chars at `positions` emphasised.
+fn highlight_markup(text: &str, positions: &[usize]) -> String {
+    highlight_runs(text, positions)
+        .into_iter()
+        .map(|(run, matched)| {
+            let escaped = gtk4::glib::markup_escape_text(run);
+            if matched {
+                format!("<b><u>{}</u></b>", escaped)
+            } else {
+                escaped.to_string()
+            }
+        })
+        .collect()
+}
+
+/// Markup for a text item: syntax highlighted in a monospace font when it
+/// is code, with search matches emphasised either way.
+fn content_markup(text: &str, language: Option<Language>, positions: &[usize]) -> String {
+    let Some(language) = language else {
+        return highlight_markup(text, positions);
+    };
+    let mut markup = String::from("<tt>");
+    let mut offset = 0;
+    for (piece, token) in highlight::highlight(text, language) {
+        let len = piece.chars().count();
+        let local: Vec<usize> = positions.iter()
+            .filter(|&&p| p >= offset && p < offset + len)
+            .map(|&p| p - offset)
+            .collect();
+        let inner = highlight_markup(piece, &local);
+        match token_color(token) {
+            Some(color) => markup.push_str(&format!("<span foreground=\"{}\">{}</span>", color, inner)),
+            None => markup.push_str(&inner),
+        }
+        offset += len;
+    }
+    markup.push_str("</tt>");
+    markup
+}
+
+// Mid-tone colours that read on both the dark and the light theme
+fn token_color(token: Token) -> Option<&'static str> {
+    match token {
+        Token::Plain => None,
+        Token::Keyword => Some("#c678dd"),
+        Token::String => Some("#50a14f"),
+        Token::Number => Some("#d19a66"),
+        Token::Comment => Some("#8a8f98"),
+        Token::Key => Some("#4f9fe0"),
+        Token::Tag => Some("#e06c75"),
+    }
+}
+
+/// Thumbnail textures for the list, by content hash. The rows are rebuilt
+/// on every refresh and keystroke, so each thumbnail is read, decrypted
+/// and decoded once and reused from then on.
+struct Thumbnails {
+    blobs: Option<BlobStore>,
+    encryption: EncryptionConfig,
+    textures: HashMap<String, gtk4::gdk::Texture>,
+}
+
+thread_local! {
+    static THUMBNAILS: RefCell<Thumbnails> = RefCell::new(Thumbnails {
+        blobs: BlobStore::open().ok(),
+        encryption: AppConfig::load().unwrap_or_default().encryption,
+        textures: HashMap::new(),
+    });
+}
+
+// Shows the thumbnail of `item` in `picture`: right away when it is
+// cached, otherwise loaded once the list has been drawn
+fn show_thumbnail(picture: &gtk4::Picture, item: &HistoryItem) {
+    if let Some(texture) = THUMBNAILS.with_borrow(|thumbnails| thumbnails.textures.get(item.id()).cloned()) {
+        picture.set_paintable(Some(&texture));
+        return;
+    }
+    let picture = picture.clone();
+    let item = item.clone();
+    glib::idle_add_local_once(move || {
+        THUMBNAILS.with_borrow_mut(|thumbnails| {
+            // Rows of an earlier refresh may have loaded it meanwhile
+            if let Some(texture) = thumbnails.textures.get(item.id()) {
+                picture.set_paintable(Some(texture));
+                return;
+            }
+            let Some(blobs) = &thumbnails.blobs else { return };
+            // Left empty while locked; the next refresh tries again
+            let Ok(png) = blobs.thumbnail(&item, ThumbnailSize::List, &thumbnails.encryption) else { return };
+            let Ok(texture) = gtk4::gdk::Texture::from_bytes(&glib::Bytes::from_owned(png)) else { return };
+            picture.set_paintable(Some(&texture));
+            thumbnails.textures.insert(item.id().to_string(), texture);
+        });
+    });
+}
+
 fn refresh_list(
     list_box: &ListBox, 
     storage: &ClipboardStorage, 
     window: &ApplicationWindow, 
     storage_rc: Rc<RefCell<ClipboardStorage>>,
-    menu_counter: Rc<RefCell<usize>>
+    menu_counter: Rc<RefCell<usize>>,
+    search_query: Rc<RefCell<String>>
 ) {
     // Clear existing children
     while let Some(child) = list_box.first_child() {
         list_box.remove(&child);
     }
 
-    for (i, item) in storage.history.iter().enumerate() {
+    let hits = storage.search(&SearchQuery::parse(&search_query.borrow()));
+    if hits.is_empty() && !search_query.borrow().is_empty() {
+        let empty_label = Label::new(Some("No matching items"));
+        empty_label.add_css_class("timestamp");
+        empty_label.set_margin_top(20);
+        list_box.append(&empty_label);
+    }
+
+    THUMBNAILS.with_borrow_mut(|thumbnails| {
+        let ids: HashSet<&str> = storage.history.iter().map(|item| item.id()).collect();
+        thumbnails.textures.retain(|id, _| ids.contains(id.as_str()));
+    });
+    for hit in &hits {
+        let i = hit.index;
+        let item = &storage.history[i];
         let row = ListBoxRow::new();
         row.set_activatable(false); // Important: stop listbox from handling activation
 
@@ -249,16 +704,32 @@ fn refresh_list(
         let content_box = gtk4::Box::new(Orientation::Horizontal, 0);
         content_box.set_hexpand(true);
         
-        let item_content = item.content.clone();
-        let item_kind = item.kind.clone();
         
-        if item.kind == "image" {
-             // Render Image
-             let picture = gtk4::Picture::for_filename(&item.content);
+        if item.kind == ClipKind::Image {
+             let picture = gtk4::Picture::new();
+             show_thumbnail(&picture, item);
              picture.set_content_fit(gtk4::ContentFit::Contain);
              picture.set_height_request(100); // Thumbnail size
              picture.set_halign(Align::Start);
+             if let Some(meta) = item.image {
+                 picture.set_tooltip_text(Some(&format!("{} × {}, {}", meta.width, meta.height, glib::format_size(meta.bytes))));
+             }
              content_box.append(&picture);
+        } else if item.kind == ClipKind::Files {
+             // Render copied files as a list
+             let files_vbox = gtk4::Box::new(Orientation::Vertical, 2);
+             files_vbox.set_hexpand(true);
+             let paths = item.file_paths();
+             for path in paths.iter().take(MAX_FILES_SHOWN) {
+                 files_vbox.append(&file_row(path));
+             }
+             if paths.len() > MAX_FILES_SHOWN {
+                 let more_label = Label::new(Some(&format!("and {} more", paths.len() - MAX_FILES_SHOWN)));
+                 more_label.add_css_class("timestamp");
+                 more_label.set_halign(Align::Start);
+                 files_vbox.append(&more_label);
+             }
+             content_box.append(&files_vbox);
         } else {
              // Render Text with "Show More" logic
              let display_content = item.content.trim();
@@ -269,6 +740,10 @@ fn refresh_list(
                  display_content.to_string()
              };
 
+             if let Some(category) = item.category {
+                 content_box.append(&category_button(category, &item.content, window));
+             }
+
              // Vertical container for Content + Button
              let content_vbox = gtk4::Box::new(Orientation::Vertical, 5);
              content_vbox.set_hexpand(true);
@@ -277,7 +752,16 @@ fn refresh_list(
              let text_event_box = gtk4::Box::new(Orientation::Horizontal, 0);
              text_event_box.set_hexpand(true);
              
-             let content_label = Label::new(Some(&display_text));
+             // Match positions index the untrimmed content
+             let leading = item.content.chars().count() - item.content.trim_start().chars().count();
+             let positions: Vec<usize> = hit.positions.iter()
+                 .filter_map(|&p| p.checked_sub(leading))
+                 .collect();
+
+             let content_label = Label::new(None);
+             // Skip positions past the cut so the "..." isn't highlighted
+             let shown: Vec<usize> = positions.iter().copied().filter(|&p| p < 500).collect();
+             content_label.set_markup(&content_markup(&display_text, item.language, &shown));
              content_label.set_hexpand(true);
              content_label.set_halign(Align::Start);
              content_label.set_ellipsize(gtk4::pango::EllipsizeMode::End);
@@ -294,12 +778,12 @@ fn refresh_list(
                  show_more_btn.add_css_class("flat"); 
                  show_more_btn.set_halign(Align::Start);
                  
-                 let full_text = display_content.to_string();
+                 let full_markup = content_markup(display_content, item.language, &positions);
                  let label_clone = content_label.clone();
                  let btn_clone = show_more_btn.clone();
                  
                  show_more_btn.connect_clicked(move |_| {
-                     label_clone.set_text(&full_text);
+                     label_clone.set_markup(&full_markup);
                      label_clone.set_lines(-1); 
                      btn_clone.set_visible(false);
                  });
@@ -338,14 +822,14 @@ fn refresh_list(
         menu_box.set_margin_end(10);
         
         // 0. Save Image (Only for Images)
-        if item.kind == "image" {
+        if item.kind == ClipKind::Image {
              let save_btn = Button::new();
              let save_lbl = Label::new(Some("Save Image"));
              save_lbl.set_halign(Align::Start);
              save_btn.set_child(Some(&save_lbl));
              save_btn.add_css_class("menu-button");
              
-             let item_content_save = item.content.clone();
+             let item_save = item.clone();
              let window_save = window.clone();
              let _mc_save = menu_counter.clone();
              let popover_save = popover.clone();
@@ -360,7 +844,7 @@ fn refresh_list(
                      .initial_name("image.png")
                      .build();
                  
-                 let src_path = std::path::PathBuf::from(&item_content_save);
+                 let src_path = std::path::PathBuf::from(&item_save.content);
                  // Try to get original filename
                  if let Some(name) = src_path.file_name() {
                      if let Some(name_str) = name.to_str() {
@@ -369,13 +853,17 @@ fn refresh_list(
                  }
 
                  let window_clone = window_save.clone(); // Clone for async block
-                 let src_path_clone = src_path.clone();
+                 let item_clone = item_save.clone();
 
                  file_dialog.save(Some(&window_clone), None::<&gtk4::gio::Cancellable>, move |result| {
                      match result {
                          Ok(file) => {
                              if let Some(target_path) = file.path() {
-                                 if let Err(e) = std::fs::copy(&src_path_clone, &target_path) {
+                                 // Written decrypted: this is an explicit export
+                                 let encryption = AppConfig::load().unwrap_or_default().encryption;
+                                 let saved = item_clone.image_data(&encryption)
+                                     .and_then(|png| std::fs::write(&target_path, png).map_err(Into::into));
+                                 if let Err(e) = saved {
                                      eprintln!("Failed to save image: {}", e);
                                  } else {
                                      println!("Image saved to {:?}", target_path);
@@ -405,15 +893,14 @@ fn refresh_list(
         let list_box_pin = list_box.clone();
         let window_pin = window.clone();
         let mc_pin = menu_counter.clone();
+        let query_pin = search_query.clone();
         pin_btn.connect_clicked(move |_| {
             {
                 let mut s = storage_pin.borrow_mut();
-                if let Some(it) = s.history.get_mut(i) {
-                    it.pinned = !it.pinned;
-                }
+                s.toggle_pin(i);
                 let _ = s.save();
             }
-            refresh_list(&list_box_pin, &storage_pin.borrow(), &window_pin, storage_pin.clone(), mc_pin.clone());
+            refresh_list(&list_box_pin, &storage_pin.borrow(), &window_pin, storage_pin.clone(), mc_pin.clone(), query_pin.clone());
         });
         menu_box.append(&pin_btn);
 
@@ -428,15 +915,14 @@ fn refresh_list(
         let list_box_del = list_box.clone();
         let window_del = window.clone();
         let mc_del = menu_counter.clone();
+        let query_del = search_query.clone();
         delete_btn.connect_clicked(move |_| {
              {
                 let mut s = storage_del.borrow_mut();
-                if i < s.history.len() {
-                    s.history.remove(i);
-                }
+                s.remove(i);
                  let _ = s.save();
             }
-            refresh_list(&list_box_del, &storage_del.borrow(), &window_del, storage_del.clone(), mc_del.clone());
+            refresh_list(&list_box_del, &storage_del.borrow(), &window_del, storage_del.clone(), mc_del.clone(), query_del.clone());
         });
         menu_box.append(&delete_btn);
 
@@ -447,71 +933,13 @@ fn refresh_list(
         // Add click gesture to content_box (not hbox) to exclude menu button
         let gesture = gtk4::GestureClick::new();
         let window_clone = window.clone();
-        let item_content_for_copy = item_content.clone();
-        let item_kind_for_copy = item_kind.clone();
-        
+        let item_for_copy = item.clone();
+
         gesture.connect_pressed(move |_, _, _, _| {
-            use std::process::{Command, Stdio};
-            use std::io::Write;
-            
-            if item_kind_for_copy == "image" {
-                println!("Copying image item");
-                
-                // Copy Image
-                let child = Command::new("wl-copy")
-                   .arg("--type").arg("image/png")
-                   .stdin(Stdio::from(std::fs::File::open(&item_content_for_copy).expect("Failed to open image file")))
-                   .spawn();
-                
-                match child {
-                    Ok(mut child) => {
-                        let _ = child.wait();
-                    }
-                    Err(_) => {
-                        // Fallback to xclip
-                         let _ = Command::new("xclip")
-                           .arg("-selection").arg("clipboard")
-                           .arg("-t").arg("image/png")
-                           .arg("-i").arg(&item_content_for_copy)
-                           .spawn()
-                           .and_then(|mut c| c.wait());
-                    }
-                }
-            } else {
-                println!("Copying text item");
-                
-                // Copy Text
-                let child = Command::new("wl-copy")
-                   .stdin(Stdio::piped())
-                   .spawn();
-                
-                match child {
-                    Ok(mut child) => {
-                        if let Some(mut stdin) = child.stdin.take() {
-                            let _ = stdin.write_all(item_content_for_copy.as_bytes());
-                        }
-                        let _ = child.wait();
-                    }
-                    Err(_) => {
-                         let child = Command::new("xclip")
-                           .arg("-selection")
-                           .arg("clipboard")
-                           .stdin(Stdio::piped())
-                           .spawn();
-                         match child {
-                             Ok(mut child) => {
-                                if let Some(mut stdin) = child.stdin.take() {
-                                    let _ = stdin.write_all(item_content_for_copy.as_bytes());
-                                }
-                                let _ = child.wait();
-                             }
-                             Err(_) => {}
-                         }
-                    }
-                }
+            match clipboard::copy_item(&item_for_copy) {
+                Ok(()) => window_clone.close(),
+                Err(e) => show_error(&window_clone, "Couldn't copy item", &e),
             }
-            
-            window_clone.close();
         });
         
         content_box.add_controller(gesture);
@@ -523,6 +951,9 @@ fn refresh_list(
         time_label.set_margin_end(10); // Spacing from menu button
 
         hbox.append(&content_box);
+        if let Some(source) = &item.source {
+            hbox.append(&source_button(source, window));
+        }
         hbox.append(&time_label);
         hbox.append(&menu_btn);
 
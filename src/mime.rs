@@ -0,0 +1,291 @@
+use std::cell::OnceCell;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::ClipKind;
+
+pub const TEXT_HTML: &str = "text/html";
+pub const URI_LIST: &str = "text/uri-list";
+pub const RTF: &str = "text/rtf";
+/// GNOME/Nautilus variant of a file list: "copy" or "cut", then URIs
+pub const GNOME_COPIED_FILES: &str = "x-special/gnome-copied-files";
+
+/// Offered by KeePassXC, 1Password, KeePass and others next to a copied
+/// password, with the value "secret"
+pub const PASSWORD_MANAGER_HINT: &str = "x-kde-passwordManagerHint";
+
+/// Formats recorded next to an item's plain text, keyed by MIME type.
+pub type Representations = BTreeMap<String, String>;
+
+/// Targets the daemon captures besides plain text. Several spellings of
+/// RTF are in use, so each is stored under the name the owner offered.
+pub const CAPTURED_TYPES: &[&str] = &[
+    TEXT_HTML, URI_LIST, GNOME_COPIED_FILES, RTF, "application/rtf", "text/richtext",
+];
+
+// Larger payloads (e.g. whole web pages) keep only their plain text
+const MAX_REPRESENTATION_BYTES: usize = 1024 * 1024;
+
+// An unresponsive selection owner must not stall the daemon
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Names under which plain text is offered to X11 clients.
+pub const TEXT_TARGETS: &[&str] = &["UTF8_STRING", "STRING", "TEXT", "text/plain;charset=utf-8", "text/plain"];
+
+/// The kind an item with these representations should be shown as.
+pub fn kind_for(representations: &Representations) -> ClipKind {
+    if representations.get(URI_LIST).is_some_and(|list| !uri_list_paths(list).is_empty()) {
+        ClipKind::Files
+    } else if representations.keys().any(|mime| is_rtf(mime)) {
+        ClipKind::RichText
+    } else if representations.contains_key(TEXT_HTML) {
+        ClipKind::Html
+    } else {
+        ClipKind::Text
+    }
+}
+
+fn is_rtf(mime: &str) -> bool {
+    matches!(mime, RTF | "application/rtf" | "text/richtext")
+}
+
+/// What the current clipboard owner offers. The targets are listed once,
+/// when first needed, and every check on the same clipboard change
+/// shares that listing. Uses wl-paste on Wayland and xclip on X11;
+/// without them nothing is offered.
+pub struct Offer {
+    tool: Option<Tool>,
+    types: OnceCell<Vec<String>>,
+}
+
+impl Offer {
+    /// The offer of the clipboard as it is now. Nothing is run yet.
+    pub fn current() -> Self {
+        Self { tool: Tool::detect(), types: OnceCell::new() }
+    }
+
+    fn types(&self) -> &[String] {
+        self.types.get_or_init(|| {
+            let Some(tool) = self.tool else { return Vec::new() };
+            run_with_timeout(tool.list_command())
+                .map(|listing| String::from_utf8_lossy(&listing).lines().map(|line| line.trim().to_string()).collect())
+                .unwrap_or_default()
+        })
+    }
+
+    fn contains(&self, mime: &str) -> bool {
+        self.types().iter().any(|offer| offer == mime)
+    }
+
+    fn read(&self, mime: &str) -> Option<Vec<u8>> {
+        run_with_timeout(self.tool?.read_command(mime))
+    }
+
+    /// Reads every format in `CAPTURED_TYPES` on offer. Formats that are
+    /// too large or not UTF-8 are skipped.
+    pub fn representations(&self) -> Representations {
+        let mut representations = Representations::new();
+        for &mime in CAPTURED_TYPES {
+            if !self.contains(mime) {
+                continue;
+            }
+            if let Some(data) = self.read(mime)
+                && data.len() <= MAX_REPRESENTATION_BYTES
+                && let Ok(text) = String::from_utf8(data)
+                && !text.trim().is_empty()
+            {
+                representations.insert(mime.to_string(), text);
+            }
+        }
+        representations
+    }
+
+    /// Whether the clipboard owner marked its contents as a password with
+    /// `PASSWORD_MANAGER_HINT`. A hint whose value can't be read counts.
+    pub fn has_password_manager_hint(&self) -> bool {
+        self.contains(PASSWORD_MANAGER_HINT)
+            && self.read(PASSWORD_MANAGER_HINT)
+                .is_none_or(|value| String::from_utf8_lossy(&value).trim() == "secret")
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Tool {
+    WlPaste,
+    Xclip,
+}
+
+impl Tool {
+    fn detect() -> Option<Self> {
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            Some(Tool::WlPaste)
+        } else if std::env::var_os("DISPLAY").is_some() {
+            Some(Tool::Xclip)
+        } else {
+            None
+        }
+    }
+
+    fn list_command(self) -> Command {
+        match self {
+            Tool::WlPaste => {
+                let mut cmd = Command::new("wl-paste");
+                cmd.arg("--list-types");
+                cmd
+            }
+            Tool::Xclip => self.read_command("TARGETS"),
+        }
+    }
+
+    fn read_command(self, target: &str) -> Command {
+        match self {
+            Tool::WlPaste => {
+                let mut cmd = Command::new("wl-paste");
+                cmd.args(["--no-newline", "--type", target]);
+                cmd
+            }
+            Tool::Xclip => {
+                let mut cmd = Command::new("xclip");
+                cmd.args(["-selection", "clipboard", "-o", "-t", target]);
+                cmd
+            }
+        }
+    }
+}
+
+/// Stdout of `cmd` if it succeeds within `READ_TIMEOUT`.
+pub(crate) fn run_with_timeout(mut cmd: Command) -> Option<Vec<u8>> {
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    let mut stdout = child.stdout.take()?;
+    let reader = thread::spawn(move || {
+        let mut buf = Vec::new();
+        stdout.read_to_end(&mut buf).map(|_| buf)
+    });
+
+    let deadline = Instant::now() + READ_TIMEOUT;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => return reader.join().ok()?.ok(),
+            Ok(Some(_)) => return None,
+            Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return None;
+            }
+        }
+    }
+}
+
+/// Local paths named by a `text/uri-list`. Comments and non-file URIs
+/// are skipped.
+pub fn uri_list_paths(uri_list: &str) -> Vec<PathBuf> {
+    uri_list
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|uri| uri.strip_prefix("file://"))
+        // Skip the optional host part, e.g. file://localhost/home/...
+        .filter_map(|rest| rest.find('/').map(|slash| &rest[slash..]))
+        .map(|path| PathBuf::from(percent_decode(path)))
+        .collect()
+}
+
+/// `text/uri-list` naming `paths`, CRLF separated as RFC 2483 asks.
+pub fn paths_to_uri_list(paths: &[PathBuf]) -> String {
+    paths.iter().map(|path| path_to_uri(path)).collect::<Vec<_>>().join("\r\n")
+}
+
+fn path_to_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for &byte in path.as_os_str().as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    uri
+}
+
+/// Makes sure a file copy carries both the standard URI list and the
+/// GNOME variant, which Nautilus and other GTK file managers paste from.
+pub fn add_file_formats(representations: &mut Representations, paths: &[PathBuf]) {
+    let uri_list = representations
+        .entry(URI_LIST.to_string())
+        .or_insert_with(|| paths_to_uri_list(paths))
+        .clone();
+    representations.entry(GNOME_COPIED_FILES.to_string()).or_insert_with(|| {
+        let uris: Vec<&str> = uri_list.lines().map(str::trim).filter(|l| !l.starts_with('#')).collect();
+        format!("copy\n{}", uris.join("\n"))
+    });
+}
+
+/// Plain-text form of a file copy: one path per line.
+pub fn files_content(paths: &[PathBuf]) -> String {
+    paths.iter().map(|path| path.to_string_lossy()).collect::<Vec<_>>().join("\n")
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(hex) = s.get(i + 1..i + 3)
+            && let Ok(byte) = u8::from_str_radix(hex, 16)
+        {
+            out.push(byte);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kind_and_uri_list() {
+        let mut representations = Representations::new();
+        assert_eq!(kind_for(&representations), ClipKind::Text);
+        representations.insert(TEXT_HTML.to_string(), "<b>hi</b>".to_string());
+        assert_eq!(kind_for(&representations), ClipKind::Html);
+        representations.insert("application/rtf".to_string(), r"{\rtf1 hi}".to_string());
+        assert_eq!(kind_for(&representations), ClipKind::RichText);
+
+        let paths = uri_list_paths("# comment\r\nfile:///home/me/My%20File.txt\r\nfile://localhost/tmp/a\r\nhttps://example.com/\r\n");
+        assert_eq!(paths, vec![PathBuf::from("/home/me/My File.txt"), PathBuf::from("/tmp/a")]);
+    }
+
+    #[test]
+    fn test_file_formats() {
+        let paths = vec![PathBuf::from("/home/me/My File.txt"), PathBuf::from("/tmp/ünï")];
+        let uri_list = paths_to_uri_list(&paths);
+        assert_eq!(uri_list, "file:///home/me/My%20File.txt\r\nfile:///tmp/%C3%BCn%C3%AF");
+        assert_eq!(uri_list_paths(&uri_list), paths);
+
+        let mut representations = Representations::new();
+        add_file_formats(&mut representations, &paths);
+        assert_eq!(kind_for(&representations), ClipKind::Files);
+        assert_eq!(
+            representations[GNOME_COPIED_FILES],
+            "copy\nfile:///home/me/My%20File.txt\nfile:///tmp/%C3%BCn%C3%AF"
+        );
+        assert_eq!(files_content(&paths), "/home/me/My File.txt\n/tmp/ünï");
+    }
+}
@@ -0,0 +1,404 @@
+use crate::category::Category;
+use crate::highlight::Language;
+use crate::{ClipboardStorage, ClipKind, HistoryItem};
+
+/// How a query is matched against item contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SearchMode {
+    /// Characters in order, not necessarily adjacent; results ranked
+    #[default]
+    Fuzzy,
+    /// Contiguous match, results in history order
+    Substring,
+    /// Regular expression, results in history order
+    Regex,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SearchQuery {
+    pub text: String,
+    pub mode: SearchMode,
+    pub case_sensitive: bool,
+    /// Only items detected as code in this language
+    pub language: Option<Language>,
+    /// Only items of this category
+    pub category: Option<Category>,
+    /// Only items copied from an app whose class or instance contains this
+    pub app: Option<String>,
+}
+
+impl SearchQuery {
+    /// Parses what the user typed into a search box. Fuzzy by default,
+    /// `'` prefix for an exact substring, `re:` prefix for a regex.
+    /// Leading `lang:<name>`, `is:<category>` and `app:<name>` words
+    /// narrow the results.
+    pub fn parse(input: &str) -> Self {
+        let mut language = None;
+        let mut category = None;
+        let mut app = None;
+        let mut input = input;
+        loop {
+            let (word, rest) = input.split_once(' ').unwrap_or((input, ""));
+            let Some((key, value)) = filter(word) else {
+                break;
+            };
+            match key {
+                "lang" => language = Language::from_name(value),
+                "is" => category = Category::from_name(value),
+                _ => app = Some(value.to_string()),
+            }
+            input = rest.trim_start();
+        }
+
+        let (mode, text) = if let Some(rest) = input.strip_prefix("re:") {
+            (SearchMode::Regex, rest)
+        } else if let Some(rest) = input.strip_prefix('\'') {
+            (SearchMode::Substring, rest)
+        } else {
+            (SearchMode::Fuzzy, input)
+        };
+        Self { text: text.to_string(), mode, case_sensitive: false, language, category, app }
+    }
+
+    /// `input` with its `key:` filter (`lang`, `is` or `app`) set to
+    /// `value`, or removed for `None`. Lets front-ends offer pickers that
+    /// edit the search text.
+    pub fn with_filter(input: &str, key: &str, value: Option<&str>) -> String {
+        let mut words = Vec::new();
+        let mut rest = input;
+        loop {
+            let (word, after) = rest.split_once(' ').unwrap_or((rest, ""));
+            let Some((word_key, _)) = filter(word) else {
+                break;
+            };
+            if word_key != key {
+                words.push(word.to_string());
+            }
+            rest = after.trim_start();
+        }
+        if let Some(value) = value {
+            words.insert(0, format!("{}:{}", key, value));
+        }
+        words.push(rest.to_string());
+        words.join(" ")
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+}
+
+/// Splits a valid filter word like `is:url` into key and value.
+fn filter(word: &str) -> Option<(&str, &str)> {
+    let (key, value) = word.split_once(':')?;
+    let valid = match key {
+        "lang" => Language::from_name(value).is_some(),
+        "is" => Category::from_name(value).is_some(),
+        "app" => !value.is_empty(),
+        _ => false,
+    };
+    valid.then_some((key, value))
+}
+
+/// A matching item: its index in `ClipboardStorage::history`, how well it
+/// matched (higher is better) and which characters of its content matched.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub index: usize,
+    pub score: i64,
+    /// Char (not byte) indices into `content`, ascending
+    pub positions: Vec<usize>,
+}
+
+// fzf-style scoring: every matched char earns SCORE_MATCH, gaps cost,
+// and matches at word starts or runs of consecutive chars earn bonuses.
+const SCORE_MATCH: i64 = 16;
+const PENALTY_GAP_START: i64 = -3;
+const PENALTY_GAP_EXTENSION: i64 = -1;
+const BONUS_BOUNDARY: i64 = 8;
+const BONUS_CAMEL: i64 = 7;
+const BONUS_CONSECUTIVE: i64 = 4;
+const BONUS_FIRST_CHAR_MULTIPLIER: i64 = 2;
+
+// Ranking blends match quality with these, each worth about one matched char
+const BONUS_RECENCY_MAX: i64 = 16;
+const BONUS_PINNED: i64 = 16;
+
+impl ClipboardStorage {
+    /// Items matching `query`, best first. An empty query matches every
+    /// item in history order. Images are only matched by an empty query
+    /// since their content is a file path.
+    ///
+    /// Fuzzy results are ranked by match score plus a bonus for recent and
+    /// pinned items; substring and regex results keep history order.
+    pub fn search(&self, query: &SearchQuery) -> Vec<SearchHit> {
+        let wanted = |item: &HistoryItem| {
+            query.language.is_none_or(|language| item.language == Some(language))
+                && query.category.is_none_or(|category| item.category == Some(category))
+                && query.app.as_deref().is_none_or(|app| item.source.as_ref().is_some_and(|s| s.mentions(app)))
+        };
+        if query.is_empty() {
+            return (0..self.history.len())
+                .filter(|&index| wanted(&self.history[index]))
+                .map(|index| SearchHit { index, score: 0, positions: Vec::new() })
+                .collect();
+        }
+
+        let matcher = match Matcher::new(query) {
+            Some(m) => m,
+            None => return Vec::new(),
+        };
+        let len = self.history.len() as i64;
+        let mut hits: Vec<SearchHit> = self.history.iter()
+            .enumerate()
+            .filter(|(_, item)| is_searchable(item) && wanted(item))
+            .filter_map(|(index, item)| {
+                let (score, positions) = matcher.find(&item.content)?;
+                let recency = BONUS_RECENCY_MAX * (len - index as i64) / len;
+                let pinned = if item.pinned { BONUS_PINNED } else { 0 };
+                Some(SearchHit { index, score: score + recency + pinned, positions })
+            })
+            .collect();
+
+        if query.mode == SearchMode::Fuzzy {
+            // Stable sort keeps history order among equal scores
+            hits.sort_by_key(|hit| std::cmp::Reverse(hit.score));
+        }
+        hits
+    }
+}
+
+fn is_searchable(item: &HistoryItem) -> bool {
+    item.kind != ClipKind::Image
+}
+
+enum Matcher {
+    Fuzzy { needle: Vec<char>, case_sensitive: bool },
+    Substring { needle: Vec<char>, case_sensitive: bool },
+    #[cfg(feature = "regex")]
+    Regex(regex::Regex),
+}
+
+impl Matcher {
+    /// `None` when the query can't match anything (e.g. an invalid regex).
+    fn new(query: &SearchQuery) -> Option<Self> {
+        let case_sensitive = query.case_sensitive;
+        let needle = fold(&query.text, case_sensitive);
+        match query.mode {
+            SearchMode::Fuzzy => Some(Matcher::Fuzzy { needle, case_sensitive }),
+            SearchMode::Substring => Some(Matcher::Substring { needle, case_sensitive }),
+            #[cfg(feature = "regex")]
+            SearchMode::Regex => regex::RegexBuilder::new(&query.text)
+                .case_insensitive(!case_sensitive)
+                .build()
+                .ok()
+                .map(Matcher::Regex),
+            #[cfg(not(feature = "regex"))]
+            SearchMode::Regex => None,
+        }
+    }
+
+    /// Match score and matched char positions, if `content` matches.
+    fn find(&self, content: &str) -> Option<(i64, Vec<usize>)> {
+        match self {
+            Matcher::Fuzzy { needle, case_sensitive } => {
+                let original: Vec<char> = content.chars().collect();
+                fuzzy_match(&original, &fold(content, *case_sensitive), needle)
+            }
+            Matcher::Substring { needle, case_sensitive } => {
+                let haystack = fold(content, *case_sensitive);
+                let start = haystack.windows(needle.len()).position(|w| w == needle.as_slice())?;
+                Some((0, (start..start + needle.len()).collect()))
+            }
+            #[cfg(feature = "regex")]
+            Matcher::Regex(re) => {
+                let m = re.find(content)?;
+                let start = content[..m.start()].chars().count();
+                let len = m.as_str().chars().count();
+                Some((0, (start..start + len).collect()))
+            }
+        }
+    }
+}
+
+/// Lowercases char by char so indices line up with the original text.
+fn fold(s: &str, case_sensitive: bool) -> Vec<char> {
+    if case_sensitive {
+        s.chars().collect()
+    } else {
+        s.chars().map(|c| c.to_lowercase().next().unwrap_or(c)).collect()
+    }
+}
+
+#[derive(PartialEq)]
+enum CharClass {
+    Delimiter,
+    Lower,
+    Upper,
+    Digit,
+    Other,
+}
+
+fn char_class(c: char) -> CharClass {
+    if c.is_lowercase() {
+        CharClass::Lower
+    } else if c.is_uppercase() {
+        CharClass::Upper
+    } else if c.is_numeric() {
+        CharClass::Digit
+    } else if c.is_alphabetic() {
+        CharClass::Other
+    } else {
+        CharClass::Delimiter
+    }
+}
+
+/// Bonus for a match at `idx` based on what precedes it.
+fn bonus_at(original: &[char], idx: usize) -> i64 {
+    if idx == 0 {
+        return BONUS_BOUNDARY;
+    }
+    let prev = char_class(original[idx - 1]);
+    let cur = char_class(original[idx]);
+    match (prev, cur) {
+        (_, CharClass::Delimiter) => 0,
+        (CharClass::Delimiter, _) => BONUS_BOUNDARY,
+        (CharClass::Lower, CharClass::Upper) => BONUS_CAMEL,
+        (p, CharClass::Digit) if p != CharClass::Digit => BONUS_CAMEL,
+        _ => 0,
+    }
+}
+
+/// fzf's v1 algorithm: a forward scan finds where the first full match
+/// ends, a backward scan from there finds the shortest window, and the
+/// window is scored. `haystack` is `original` after case folding.
+fn fuzzy_match(original: &[char], haystack: &[char], needle: &[char]) -> Option<(i64, Vec<usize>)> {
+    if needle.is_empty() {
+        return Some((0, Vec::new()));
+    }
+
+    let mut pidx = 0;
+    let mut end = 0;
+    for (idx, &c) in haystack.iter().enumerate() {
+        if c == needle[pidx] {
+            pidx += 1;
+            if pidx == needle.len() {
+                end = idx;
+                break;
+            }
+        }
+    }
+    if pidx < needle.len() {
+        return None;
+    }
+
+    let mut pidx = needle.len();
+    let mut start = end;
+    for idx in (0..=end).rev() {
+        if haystack[idx] == needle[pidx - 1] {
+            pidx -= 1;
+            if pidx == 0 {
+                start = idx;
+                break;
+            }
+        }
+    }
+
+    let mut score = 0;
+    let mut positions = Vec::with_capacity(needle.len());
+    let mut pidx = 0;
+    let mut in_gap = false;
+    let mut consecutive = 0;
+    let mut first_bonus = 0;
+    for (idx, &c) in haystack.iter().enumerate().take(end + 1).skip(start) {
+        if pidx < needle.len() && c == needle[pidx] {
+            let mut bonus = bonus_at(original, idx);
+            if consecutive == 0 {
+                first_bonus = bonus;
+            } else {
+                // A run keeps the bonus of the boundary it started at
+                if bonus >= BONUS_BOUNDARY && bonus > first_bonus {
+                    first_bonus = bonus;
+                }
+                bonus = bonus.max(first_bonus).max(BONUS_CONSECUTIVE);
+            }
+            score += SCORE_MATCH + if pidx == 0 { bonus * BONUS_FIRST_CHAR_MULTIPLIER } else { bonus };
+            positions.push(idx);
+            in_gap = false;
+            consecutive += 1;
+            pidx += 1;
+        } else {
+            score += if in_gap { PENALTY_GAP_EXTENSION } else { PENALTY_GAP_START };
+            in_gap = true;
+            consecutive = 0;
+            first_bonus = 0;
+        }
+    }
+    Some((score, positions))
+}
+
+/// Splits `text` into runs of matched and unmatched chars for display.
+/// `positions` are char indices into `text`; out-of-range ones are ignored.
+pub fn highlight_runs<'a>(text: &'a str, positions: &[usize]) -> Vec<(&'a str, bool)> {
+    let mut runs = Vec::new();
+    let mut run_start = 0;
+    let mut run_matched = false;
+    let mut wanted = positions.iter().peekable();
+    for (char_idx, (byte_idx, _)) in text.char_indices().enumerate() {
+        while wanted.next_if(|&&p| p < char_idx).is_some() {}
+        let matched = wanted.next_if(|&&p| p == char_idx).is_some();
+        if matched != run_matched && byte_idx > run_start {
+            runs.push((&text[run_start..byte_idx], run_matched));
+            run_start = byte_idx;
+        }
+        run_matched = matched;
+    }
+    if run_start < text.len() {
+        runs.push((&text[run_start..], run_matched));
+    }
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fuzzy(haystack: &str, needle: &str) -> Option<(i64, Vec<usize>)> {
+        let original: Vec<char> = haystack.chars().collect();
+        fuzzy_match(&original, &fold(haystack, false), &fold(needle, false))
+    }
+
+    #[test]
+    fn test_fuzzy_prefers_word_boundaries() {
+        let (boundary, positions) = fuzzy("foo_bar", "fb").unwrap();
+        assert_eq!(positions, vec![0, 4]);
+        let (inner, _) = fuzzy("xfoxbx", "fb").unwrap();
+        assert!(boundary > inner);
+
+        // Shortest window wins over the first occurrence
+        let (_, positions) = fuzzy("a__a_b", "ab").unwrap();
+        assert_eq!(positions, vec![3, 5]);
+        assert!(fuzzy("abc", "acb").is_none());
+    }
+
+    #[test]
+    fn test_highlight_runs() {
+        assert_eq!(
+            highlight_runs("héllo wörld", &[0, 1, 7]),
+            vec![("hé", true), ("llo w", false), ("ö", true), ("rld", false)]
+        );
+        assert_eq!(highlight_runs("abc", &[]), vec![("abc", false)]);
+    }
+
+    #[test]
+    fn test_with_filter() {
+        assert_eq!(SearchQuery::with_filter("foo", "is", Some("url")), "is:url foo");
+        assert_eq!(SearchQuery::with_filter("is:url lang:sh foo", "is", Some("code")), "is:code lang:sh foo");
+        assert_eq!(SearchQuery::with_filter("is:url", "is", None), "");
+        assert_eq!(SearchQuery::with_filter("app:kitty x", "app", Some("firefox")), "app:firefox x");
+        let query = SearchQuery::parse(&SearchQuery::with_filter("'bar", "is", Some("path")));
+        assert_eq!((query.category, query.mode, query.text.as_str()), (Some(Category::Path), SearchMode::Substring, "bar"));
+        // Unknown filter values are searched for as text
+        assert_eq!(SearchQuery::parse("is:nothing").text, "is:nothing");
+    }
+}
@@ -0,0 +1,184 @@
+use anyhow::{Result, Context};
+use serde::{Deserialize, Serialize};
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{Atom, AtomEnum, ConnectionExt as _, Window};
+use x11rb::rust_connection::RustConnection;
+use x11rb::NONE;
+
+use crate::mime;
+
+/// The application a clip was copied from.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SourceApp {
+    /// Application class: WM_CLASS on X11, the window class on Hyprland
+    pub class: String,
+    /// WM_CLASS instance name, often the executable name
+    pub instance: String,
+    /// Title of the window at the time of the copy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+impl SourceApp {
+    /// Whether `name` (a class or instance, any case) names this app.
+    pub fn matches(&self, name: &str) -> bool {
+        self.class.eq_ignore_ascii_case(name) || self.instance.eq_ignore_ascii_case(name)
+    }
+
+    /// Whether the class or instance contains `needle`, ignoring case.
+    /// Used for search filters, where "terminal" should find
+    /// "org.gnome.Terminal".
+    pub fn mentions(&self, needle: &str) -> bool {
+        let needle = needle.to_lowercase();
+        self.class.to_lowercase().contains(&needle) || self.instance.to_lowercase().contains(&needle)
+    }
+}
+
+/// Works out which application owns the clipboard, where the display
+/// server lets us. Hyprland is asked for its active window; on X11 the
+/// selection owner is tried first, then `_NET_ACTIVE_WINDOW`. Other
+/// Wayland compositors don't tell, so `detect` returns `None` there.
+///
+/// The X11 connection is kept between copies and only made again after
+/// it failed.
+#[derive(Default)]
+pub struct SourceDetector {
+    x11: Option<X11>,
+}
+
+impl SourceDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether this session lets `detect` find anything: Hyprland or X11,
+    /// but not other Wayland compositors.
+    pub fn is_supported() -> bool {
+        std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some()
+            || (std::env::var_os("DISPLAY").is_some() && std::env::var_os("WAYLAND_DISPLAY").is_none())
+    }
+
+    /// The application that owns the clipboard right now.
+    pub fn detect(&mut self) -> Option<SourceApp> {
+        if std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some() {
+            return hyprland();
+        }
+        if !Self::is_supported() {
+            return None;
+        }
+        let result = match self.x11.take() {
+            Some(x11) => Ok(x11),
+            None => X11::connect(),
+        }
+        .and_then(|x11| {
+            let app = x11.clipboard_owner()?;
+            self.x11 = Some(x11);
+            Ok(app)
+        });
+        result.unwrap_or_else(|e| {
+            eprintln!("Could not look up the clipboard's source window: {}", e);
+            None
+        })
+    }
+}
+
+fn hyprland() -> Option<SourceApp> {
+    let mut cmd = std::process::Command::new("hyprctl");
+    cmd.args(["activewindow", "-j"]);
+    let output = mime::run_with_timeout(cmd)?;
+    let window: serde_json::Value = serde_json::from_slice(&output).ok()?;
+    let field = |name: &str| window.get(name).and_then(|v| v.as_str()).filter(|s| !s.is_empty());
+    let class = field("class").or_else(|| field("initialClass"))?.to_string();
+    Some(SourceApp {
+        instance: field("initialClass").unwrap_or(&class).to_string(),
+        title: field("title").map(str::to_string),
+        class,
+    })
+}
+
+// A connection to the X server with the atoms the lookup needs
+struct X11 {
+    conn: RustConnection,
+    root: Window,
+    clipboard: Atom,
+    net_active_window: Atom,
+    net_wm_name: Atom,
+    utf8_string: Atom,
+}
+
+impl X11 {
+    fn connect() -> Result<Self> {
+        let (conn, screen_num) = x11rb::connect(None)
+            .context("Failed to connect to the X server")?;
+        let root = conn.setup().roots[screen_num].root;
+        let intern = |name: &str| -> Result<Atom> {
+            Ok(conn.intern_atom(false, name.as_bytes())?.reply()?.atom)
+        };
+        Ok(Self {
+            root,
+            clipboard: intern("CLIPBOARD")?,
+            net_active_window: intern("_NET_ACTIVE_WINDOW")?,
+            net_wm_name: intern("_NET_WM_NAME")?,
+            utf8_string: intern("UTF8_STRING")?,
+            conn,
+        })
+    }
+
+    fn clipboard_owner(&self) -> Result<Option<SourceApp>> {
+        // Toolkits often own the selection from a hidden window without a
+        // class, so the focused window is the fallback
+        let owner = self.conn.get_selection_owner(self.clipboard)?.reply()?.owner;
+        let active = self.conn
+            .get_property(false, self.root, self.net_active_window, AtomEnum::WINDOW, 0, 1)?
+            .reply()?
+            .value32()
+            .and_then(|mut windows| windows.next())
+            .unwrap_or(NONE);
+
+        Ok([owner, active]
+            .into_iter()
+            .filter(|&window| window != NONE)
+            .find_map(|window| window_app(&self.conn, window, self.net_wm_name, self.utf8_string)))
+    }
+}
+
+/// WM_CLASS and title of `window`, if it has a class. Windows can vanish
+/// at any moment, so errors just mean "unknown".
+fn window_app(conn: &RustConnection, window: Window, net_wm_name: Atom, utf8_string: Atom) -> Option<SourceApp> {
+    let property = |property: Atom, kind: Atom| -> Option<Vec<u8>> {
+        let reply = conn.get_property(false, window, property, kind, 0, 1024).ok()?.reply().ok()?;
+        (!reply.value.is_empty()).then_some(reply.value)
+    };
+
+    // Two NUL-terminated strings: instance, then class
+    let wm_class = property(AtomEnum::WM_CLASS.into(), AtomEnum::STRING.into())?;
+    let mut names = wm_class
+        .split(|&b| b == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| String::from_utf8_lossy(name).into_owned());
+    let instance = names.next()?;
+    let class = names.next().unwrap_or_else(|| instance.clone());
+
+    let title = property(net_wm_name, utf8_string)
+        .or_else(|| property(AtomEnum::WM_NAME.into(), AtomEnum::STRING.into()))
+        .map(|title| String::from_utf8_lossy(&title).into_owned());
+    Some(SourceApp { class, instance, title })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        let app = SourceApp {
+            class: "KeePassXC".to_string(),
+            instance: "keepassxc".to_string(),
+            title: None,
+        };
+        assert!(app.matches("keepassxc"));
+        assert!(app.matches("KEEPASSXC"));
+        assert!(!app.matches("keepass"));
+        assert!(app.mentions("keepass"));
+    }
+}
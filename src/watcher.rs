@@ -0,0 +1,184 @@
+use std::io::{BufRead, BufReader};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+use anyhow::{Result, Context, bail};
+use x11rb::connection::Connection;
+use x11rb::protocol::Event;
+use x11rb::protocol::xfixes::{ConnectionExt as _, SelectionEventMask};
+use x11rb::protocol::xproto::ConnectionExt as _;
+use x11rb::rust_connection::RustConnection;
+
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Blocks until the clipboard may have changed. Implementations may wake
+/// up spuriously; callers compare contents before recording anything.
+pub trait ClipboardWatcher {
+    fn wait(&mut self) -> Result<()>;
+
+    fn name(&self) -> &'static str;
+}
+
+/// Picks the best watcher for the current session: wl-paste on Wayland,
+/// XFixes on X11, and polling when neither is available.
+pub fn detect() -> Box<dyn ClipboardWatcher> {
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        match WaylandWatcher::new() {
+            Ok(w) => return Box::new(w),
+            Err(e) => eprintln!("Wayland clipboard watcher unavailable: {}", e),
+        }
+    }
+    if std::env::var_os("DISPLAY").is_some() {
+        match XFixesWatcher::new() {
+            Ok(w) => return Box::new(w),
+            Err(e) => eprintln!("X11 clipboard watcher unavailable: {}", e),
+        }
+    }
+    Box::new(PollingWatcher::new(POLL_INTERVAL))
+}
+
+/// The original fixed-interval loop.
+pub struct PollingWatcher {
+    interval: Duration,
+}
+
+impl PollingWatcher {
+    pub fn new(interval: Duration) -> Self {
+        Self { interval }
+    }
+}
+
+impl ClipboardWatcher for PollingWatcher {
+    fn wait(&mut self) -> Result<()> {
+        thread::sleep(self.interval);
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "polling"
+    }
+}
+
+/// Listens for XFixes selection-owner notifications on the CLIPBOARD
+/// selection, so the daemon only wakes when something is copied.
+pub struct XFixesWatcher {
+    conn: RustConnection,
+}
+
+impl XFixesWatcher {
+    pub fn new() -> Result<Self> {
+        Self::connect(None)
+    }
+
+    pub fn connect(display: Option<&str>) -> Result<Self> {
+        let (conn, screen_num) = x11rb::connect(display)
+            .context("Failed to connect to the X server")?;
+        let root = conn.setup().roots[screen_num].root;
+
+        conn.xfixes_query_version(5, 0)?
+            .reply()
+            .context("XFixes extension is not available")?;
+        let clipboard = conn.intern_atom(false, b"CLIPBOARD")?.reply()?.atom;
+        conn.xfixes_select_selection_input(
+            root,
+            clipboard,
+            SelectionEventMask::SET_SELECTION_OWNER
+                | SelectionEventMask::SELECTION_WINDOW_DESTROY
+                | SelectionEventMask::SELECTION_CLIENT_CLOSE,
+        )?;
+        conn.flush()?;
+
+        Ok(Self { conn })
+    }
+}
+
+impl ClipboardWatcher for XFixesWatcher {
+    fn wait(&mut self) -> Result<()> {
+        loop {
+            if let Event::XfixesSelectionNotify(_) = self.conn.wait_for_event()? {
+                return Ok(());
+            }
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "xfixes"
+    }
+}
+
+/// Uses `wl-paste --watch` (wl-clipboard), which follows the Wayland
+/// data-control protocol and runs a command on every selection change.
+/// The command prints one line per change for us to read.
+pub struct WaylandWatcher {
+    child: Child,
+    events: BufReader<ChildStdout>,
+}
+
+impl WaylandWatcher {
+    pub fn new() -> Result<Self> {
+        let mut child = Command::new("wl-paste")
+            .args(["--watch", "sh", "-c", "cat > /dev/null; echo"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("Failed to start wl-paste (is wl-clipboard installed?)")?;
+        let stdout = child.stdout.take().context("wl-paste has no stdout")?;
+        Ok(Self { child, events: BufReader::new(stdout) })
+    }
+}
+
+impl ClipboardWatcher for WaylandWatcher {
+    fn wait(&mut self) -> Result<()> {
+        let mut line = String::new();
+        if self.events.read_line(&mut line)? == 0 {
+            // Typically the compositor lacks the data-control protocol
+            let status = self.child.wait()?;
+            bail!("wl-paste exited ({})", status);
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "wayland"
+    }
+}
+
+impl Drop for WaylandWatcher {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use x11rb::protocol::xproto::{CreateWindowAux, WindowClass};
+    use x11rb::CURRENT_TIME;
+
+    // Run with `xvfb-run cargo test -- --ignored` to exercise it
+    #[test]
+    #[ignore = "needs an X display"]
+    fn test_xfixes_notifies_on_owner_change() {
+        let mut watcher = XFixesWatcher::new().unwrap();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = tx.send(watcher.wait().is_ok());
+        });
+
+        // Take ownership of CLIPBOARD from a second client
+        let (conn, screen_num) = x11rb::connect(None).unwrap();
+        let screen = &conn.setup().roots[screen_num];
+        let win = conn.generate_id().unwrap();
+        conn.create_window(
+            0, win, screen.root, 0, 0, 1, 1, 0,
+            WindowClass::INPUT_OUTPUT, 0, &CreateWindowAux::new(),
+        ).unwrap();
+        let clipboard = conn.intern_atom(false, b"CLIPBOARD").unwrap().reply().unwrap().atom;
+        conn.set_selection_owner(win, clipboard, CURRENT_TIME).unwrap();
+        conn.flush().unwrap();
+
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(true));
+    }
+}